@@ -1,16 +1,36 @@
-use bevy::prelude::*;
+use std::{fs::File, io, path::Path, sync::Mutex};
+
+use bevy::{prelude::*, utils::HashMap};
+use gpu_copy::ExportFrameCounter;
 
 use rand::prelude::*;
+use rand::rngs::StdRng;
+use serde::{Deserialize, Serialize};
 
-use crate::ai_framework::Environment;
-use crate::movement::Velocity;
+use crate::movement::{Acceleration, Velocity};
 use crate::ai_framework::Sensor;
 use crate::ai_framework::Sensing;
+use crate::ai_framework::SensingContext;
+use crate::collision_detection::Collider;
+use crate::health::Health;
+use crate::input::PlayerInput;
+use crate::neat::NeatBrain;
 use crate::schedule::InGameSet;
 use crate::vision::VisionView;
 
 const ROTATION_SPEED: f32 = 2.5;
-const SPEED: f32 = 15.0;
+const THRUST: f32 = 20.0;
+const SHOOT_THRESHOLD: f32 = 0.5;
+const ROTATION_THRESHOLD: f32 = 0.1;
+const FORWARD_THRESHOLD: f32 = 0.4;
+const BACKWARD_THRESHOLD: f32 = 0.5;
+const BRAKE_THRESHOLD: f32 = 0.8;
+/// Reversing only gets this fraction of the forward thrust.
+const REVERSE_THRUST_RATIO: f32 = 0.5;
+/// Fraction of its velocity a braking agent sheds per second.
+const BRAKE_DECELERATION: f32 = 3.0;
+/// Movement output between the forward and backward thresholds, standing still.
+const IDLE_MOVEMENT: f32 = (FORWARD_THRESHOLD + BACKWARD_THRESHOLD) / 2.0;
 
 
 pub enum Action
@@ -27,15 +47,42 @@ enum ActionIndex
   Rotation = 0,
   Movement = 1,
   Shooting = 2,
+  Brake = 3,
 }
 
 
+/// Number of outputs a brain has to produce, one per `ActionIndex`.
+pub const NUM_ACTIONS: usize = 4;
+/// Outputs an agent needs to act at all. Brains from before the brake action
+/// still work, they just never brake.
+const MIN_ACTIONS: usize = ActionIndex::Shooting as usize + 1;
+
+
 /// What is the purpose of the agent - to make decisions and affect other
 /// agents/environments
 #[derive(Component, Debug, Default)]
 pub struct Agent;
 
 
+/// How fast an agent turns, in radians per second, and how hard its thrust
+/// accelerates it. Agents without one use the defaults.
+#[derive(Component, Debug, Clone, Copy)]
+pub struct Locomotion
+{
+  pub rotation_speed: f32,
+  pub thrust: f32,
+}
+
+
+impl Default for Locomotion
+{
+  fn default() -> Self
+  {
+    Self { rotation_speed: ROTATION_SPEED, thrust: THRUST }
+  }
+}
+
+
 /// What is the purpose of an environment - to provide RESOURCES and SENSORY
 /// data
 #[derive(Component, Debug, Default)]
@@ -49,14 +96,147 @@ pub enum Brain
 {
   Random(RandomBrain),
   Human,
-  Neat
+  Neat(NeatBrain),
+  External(ExternalBrain),
+  Replay(ReplayBrain),
 }
 
 
 #[derive(Component, Debug, Clone,)]
 pub struct RandomBrain
 {
+  seed: u64,
+  rng: StdRng,
+}
+
+
+/// Brain controlled from outside of the app, see `HeadlessSim::set_action`.
+/// Keeps repeating `action` until it is replaced.
+#[derive(Debug, Clone)]
+pub struct ExternalBrain
+{
+  pub action: [f32; NUM_ACTIONS],
+}
+
+
+/// Brain playing back the actions an agent applied in a recorded run, one per
+/// `update_agents` run, see `ActionRecorder::replay_brain`. Stands still once
+/// the recording runs out.
+#[derive(Debug, Clone, Default)]
+pub struct ReplayBrain
+{
+  actions: Vec<[f32; NUM_ACTIONS]>,
+  next: usize,
+}
+
+
+/// Action vector an agent applied during one `update_agents` run.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RecordedAction
+{
+  /// `ExportFrameCounter` during the run, the render frame count that also
+  /// stamps the exported vision frames. 0 in apps without `gpu_copy`.
+  pub frame_id: u64,
+  /// `Entity::to_bits` of the agent.
+  pub agent: u64,
+  pub action: [f32; NUM_ACTIONS],
+}
+
+
+/// Records every action applied by `update_agents` while the resource exists,
+/// keyed by the `ExportFrameCounter` of the frame it was applied in.
+/// Entities are only stable between runs spawning the same entities in the
+/// same order, so replay with the `SimulationSeed` the recording was made
+/// with.
+#[derive(Resource, Debug, Default, Clone, Serialize, Deserialize)]
+pub struct ActionRecorder
+{
+  pub actions: Vec<RecordedAction>,
+}
+
+
+/// Sensations every agent received during the last `update_agents` run,
+/// keyed by agent entity. Only recorded while the resource exists.
+#[derive(Resource, Debug, Default)]
+pub struct AgentObservations(pub HashMap<Entity, Vec<f32>>);
+
+
+/// Version of the brain file format written by `AgentBrain::save`. Version 1
+/// predates the brake action; such files are still loaded, with the brake
+/// never used.
+pub const BRAIN_FILE_VERSION: u32 = 2;
+/// Actions in version 1 brain files.
+const V1_NUM_ACTIONS: usize = 3;
+
+
+#[derive(Debug, Deserialize)]
+struct BrainFileVersion
+{
+  version: u32,
+}
+
+
+/// On-disk form of a `Brain`, stored as pretty printed JSON.
+#[derive(Debug, Serialize, Deserialize)]
+struct BrainFile
+{
+  version: u32,
+  brain: SavedBrain,
+}
+
+
+#[derive(Debug, Serialize, Deserialize)]
+pub(crate) enum SavedBrain
+{
+  Random { seed: u64 },
+  Human,
+  Neat(NeatBrain),
+  External,
+  Replay { actions: Vec<[f32; NUM_ACTIONS]>, next: usize },
+}
+
+
+#[derive(Debug, Deserialize)]
+struct BrainFileV1
+{
+  brain: SavedBrainV1,
+}
+
+
+#[derive(Debug, Deserialize)]
+enum SavedBrainV1
+{
+  Random { seed: u64 },
+  Human,
+  Neat(NeatBrain),
+  External,
+  Replay { actions: Vec<[f32; V1_NUM_ACTIONS]>, next: usize },
+}
 
+
+impl From<SavedBrainV1> for SavedBrain
+{
+  fn from(brain: SavedBrainV1) -> Self
+  {
+    match brain
+    {
+      SavedBrainV1::Random { seed } => SavedBrain::Random { seed },
+      SavedBrainV1::Human => SavedBrain::Human,
+      SavedBrainV1::Neat(mut brain) =>
+      {
+        brain.add_outputs(V1_NUM_ACTIONS);
+        SavedBrain::Neat(brain)
+      },
+      SavedBrainV1::External => SavedBrain::External,
+      SavedBrainV1::Replay { actions, next } =>
+      {
+        let actions = actions.into_iter()
+          .map(|old| std::array::from_fn(|action| old.get(action).copied().unwrap_or(0.0)))
+          .collect();
+        SavedBrain::Replay { actions, next }
+      },
+    }
+  }
 }
 
 
@@ -69,11 +249,112 @@ impl Default for Brain
 }
 
 
+impl Brain
+{
+  /// Loads a brain written by `AgentBrain::save`, upgrading older versions.
+  /// Random brains restart from their original seed.
+  pub fn load(path: &Path) -> io::Result<Brain>
+  {
+    let file: serde_json::Value = serde_json::from_reader(io::BufReader::new(File::open(path)?))?;
+    let brain = match BrainFileVersion::deserialize(&file)?.version
+    {
+      BRAIN_FILE_VERSION => BrainFile::deserialize(file)?.brain,
+      1 => BrainFileV1::deserialize(file)?.brain.into(),
+      version => return Err(io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("Unsupported brain file version {}, expected {}", version, BRAIN_FILE_VERSION),
+      )),
+    };
+
+    Ok(match brain
+    {
+      SavedBrain::Random { seed } => Brain::Random(RandomBrain::seeded(seed)),
+      SavedBrain::Human => Brain::Human,
+      SavedBrain::Neat(brain) if !brain.has_outputs() => return Err(io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("NEAT brain doesn't have {} output nodes", NUM_ACTIONS),
+      )),
+      SavedBrain::Neat(brain) => Brain::Neat(brain),
+      SavedBrain::External => Brain::External(ExternalBrain::default()),
+      SavedBrain::Replay { actions, next } => Brain::Replay(ReplayBrain { actions, next }),
+    })
+  }
+
+  pub fn variant_name(&self) -> &'static str
+  {
+    match self
+    {
+      Brain::Random(_) => "Random",
+      Brain::Human => "Human",
+      Brain::Neat(_) => "Neat",
+      Brain::External(_) => "External",
+      Brain::Replay(_) => "Replay",
+    }
+  }
+}
+
+
 impl Default for RandomBrain
 {
   fn default() -> Self
   {
-    RandomBrain {}
+    RandomBrain::seeded(StdRng::from_entropy().gen())
+  }
+}
+
+
+impl Default for ExternalBrain
+{
+  fn default() -> Self
+  {
+    let mut action = [0.0; NUM_ACTIONS];
+    action[ActionIndex::Movement as usize] = IDLE_MOVEMENT;
+    Self { action }
+  }
+}
+
+
+impl ActionRecorder
+{
+  pub fn save(&self, path: &Path) -> io::Result<()>
+  {
+    serde_json::to_writer(io::BufWriter::new(File::create(path)?), self)?;
+    Ok(())
+  }
+
+  pub fn load(path: &Path) -> io::Result<Self>
+  {
+    Ok(serde_json::from_reader(io::BufReader::new(File::open(path)?))?)
+  }
+
+  /// Brain replaying the recorded actions of `agent`, in frame order. Actions
+  /// recorded in the same frame keep the order they were applied in.
+  pub fn replay_brain(&self, agent: Entity) -> ReplayBrain
+  {
+    let mut recorded: Vec<_> = self.actions.iter()
+      .filter(|recorded| recorded.agent == agent.to_bits())
+      .collect();
+    recorded.sort_by_key(|recorded| recorded.frame_id);
+
+    ReplayBrain
+    {
+      actions: recorded.into_iter().map(|recorded| recorded.action).collect(),
+      next: 0,
+    }
+  }
+}
+
+
+impl RandomBrain
+{
+  pub fn seeded(seed: u64) -> Self
+  {
+    RandomBrain { seed, rng: StdRng::seed_from_u64(seed) }
+  }
+
+  pub fn seed(&self) -> u64
+  {
+    self.seed
   }
 }
 
@@ -98,6 +379,24 @@ pub trait AgentBrain
 {
   // TODO: How to collect inputs?
   fn process_input(&mut self, sensations: &Vec<f32>) -> Vec<f32>;
+
+  /// Processes several sensation vectors at once, returning one output per
+  /// input. Brains that can share work between inputs should override this.
+  fn process_batch(&mut self, batch: &[Vec<f32>]) -> Vec<Vec<f32>>
+  {
+    batch.iter().map(|sensations| self.process_input(sensations)).collect()
+  }
+
+  /// Writes the brain to `path` in a form `Brain::load` can read back.
+  fn save(&self, path: &Path) -> io::Result<()>;
+}
+
+
+pub(crate) fn save_brain(brain: SavedBrain, path: &Path) -> io::Result<()>
+{
+  let file = BrainFile { version: BRAIN_FILE_VERSION, brain };
+  serde_json::to_writer_pretty(io::BufWriter::new(File::create(path)?), &file)?;
+  Ok(())
 }
 
 
@@ -111,13 +410,76 @@ impl AgentBrain for Brain
         brain.process_input(sensations)
       },
       Brain::Human => {
+        // Player input is not available here, see `human_actions`.
         vec![]
       }
-      Brain::Neat => {
-        vec![]
+      Brain::Neat(brain) => {
+        brain.process_input(sensations)
       }
+      Brain::External(brain) => {
+        brain.process_input(sensations)
+      }
+      Brain::Replay(brain) => {
+        brain.process_input(sensations)
+      }
+    }
+  }
+
+  fn process_batch(&mut self, batch: &[Vec<f32>]) -> Vec<Vec<f32>>
+  {
+    match self
+    {
+      Brain::Random(brain) => brain.process_batch(batch),
+      Brain::Human => vec![vec![]; batch.len()],
+      Brain::Neat(brain) => brain.process_batch(batch),
+      Brain::External(brain) => brain.process_batch(batch),
+      Brain::Replay(brain) => brain.process_batch(batch),
     }
   }
+
+  fn save(&self, path: &Path) -> io::Result<()>
+  {
+    match self
+    {
+      Brain::Random(brain) => brain.save(path),
+      Brain::Human => save_brain(SavedBrain::Human, path),
+      Brain::Neat(brain) => brain.save(path),
+      Brain::External(brain) => brain.save(path),
+      Brain::Replay(brain) => brain.save(path),
+    }
+  }
+}
+
+
+impl AgentBrain for ExternalBrain
+{
+  fn process_input(&mut self, _sensations: &Vec<f32>) -> Vec<f32>
+  {
+    self.action.to_vec()
+  }
+
+  fn save(&self, path: &Path) -> io::Result<()>
+  {
+    save_brain(SavedBrain::External, path)
+  }
+}
+
+
+impl AgentBrain for ReplayBrain
+{
+  fn process_input(&mut self, _sensations: &Vec<f32>) -> Vec<f32>
+  {
+    let action = self.actions.get(self.next)
+      .copied()
+      .unwrap_or_else(|| ExternalBrain::default().action);
+    self.next += 1;
+    action.to_vec()
+  }
+
+  fn save(&self, path: &Path) -> io::Result<()>
+  {
+    save_brain(SavedBrain::Replay { actions: self.actions.clone(), next: self.next }, path)
+  }
 }
 
 
@@ -125,12 +487,17 @@ impl AgentBrain for RandomBrain
 {
   fn process_input(&mut self, _sensations: &Vec<f32>) -> Vec<f32>
   {
-    let mut rng = rand::thread_rng();
-    let rotation = rng.gen_range(-1.0f32..=1.0f32);
-    let movement = rng.gen_range(-1.0f32..=1.0f32);
-    let shoot = rng.gen_range(0.0f32..=1.0f32);
+    let rotation = self.rng.gen_range(-1.0f32..=1.0f32);
+    let movement = self.rng.gen_range(-1.0f32..=1.0f32);
+    let shoot = self.rng.gen_range(0.0f32..=1.0f32);
+    let brake = self.rng.gen_range(0.0f32..=1.0f32);
 
-    vec![rotation, movement, shoot]
+    vec![rotation, movement, shoot, brake]
+  }
+
+  fn save(&self, path: &Path) -> io::Result<()>
+  {
+    save_brain(SavedBrain::Random { seed: self.seed }, path)
   }
 }
 
@@ -138,11 +505,22 @@ impl AgentBrain for RandomBrain
 pub struct AiAgentPlugin;
 
 
+type AgentMotionQuery<'w, 's> = Query<'w, 's, (&'static mut Transform, &'static mut Velocity, &'static mut Acceleration, Option<&'static Locomotion>), With<Agent>>;
+
+
+/// When enabled `update_agents` first senses for every agent, then runs all
+/// brains in parallel, see `brain_process_batched`, and only afterwards
+/// applies their actions. Worth it for large populations.
+#[derive(Resource, Debug, Default, Clone, Copy)]
+pub struct BatchedInference(pub bool);
+
+
 impl Plugin for AiAgentPlugin
 {
   fn build(&self, app: &mut App)
   {
     app.add_systems(Update, update_agents.in_set(InGameSet::EntityUpdates))
+       .init_resource::<BatchedInference>()
        .add_event::<ShootEvent>();
   }
 }
@@ -150,52 +528,112 @@ impl Plugin for AiAgentPlugin
 
 fn collect_sensations(sensors_query: &Query<&Sensor>,
                       children: &Children,
-                      vision_view: &VisionView,
+                      context: &SensingContext,
 ) -> Vec<f32>
 {
-  // TODO: make sure to deal sensations order once you have more than one type
-  // of sensor
-  let mut sensations = vec![];
-  for &child in children.iter()
+  // Vision rows always come first, ordered by vision id, then proximity
+  // readings and the self state last, independent of the order the sensors
+  // were spawned in, so brain inputs keep a stable layout. An agent can carry
+  // several visions, one on itself and more on its children.
+  let mut visions = vec![];
+  let mut proximity = vec![];
+  let mut self_state = vec![];
+  for entity in std::iter::once(context.agent).chain(children.iter().copied())
   {
-    if let Ok(sensor) = sensors_query.get(child)
+    if let Ok(sensor) = sensors_query.get(entity)
     {
+      let Some(sensing) = sensor.sense(sensor.environment(), context) else { continue; };
       match sensor
       {
-        Sensor::Vision(sensing) =>
-        {
-  //        info!("Id of vision: {}", sensing.id);
-  //        info!("Image address in update_agents: {:?}", &sensing.visual_sensor);
-          if let Some(sensing) = sensing.sense(Environment::VisibleEnvironment{}, &vision_view)
-          {
-//            println!("Sensing: {:?}", sensing.len());
-            sensations.extend(sensing);
-          }
-          else
-          {
-  //          println!("No sensing");
-          }
-        }
+        Sensor::Vision(vision) => visions.push((vision.id, sensing)),
+        Sensor::Proximity { .. } => proximity.extend(sensing),
+        Sensor::SelfState(_) => self_state.extend(sensing),
       }
     }
   }
 
+  visions.sort_by_key(|(id, _)| *id);
+
+  let mut sensations: Vec<f32> = visions.into_iter().flat_map(|(_, sensing)| sensing).collect();
+  sensations.extend(proximity);
+  sensations.extend(self_state);
   sensations
 }
 
 
-fn brain_process(brain_query: &mut Query<&mut Brain>,
+/// Translates the player controls into the same action vector the AI brains
+/// produce, using the `InputBindings` of the spaceship controls.
+fn human_actions(player_input: &PlayerInput) -> Vec<f32>
+{
+  let mut actions = vec![0.0; NUM_ACTIONS];
+
+  // Both are positive to the left.
+  actions[ActionIndex::Rotation as usize] = player_input.yaw();
+
+  let thrust = player_input.thrust();
+  actions[ActionIndex::Movement as usize] = if thrust < 0.0
+  {
+    1.0
+  }
+  else if thrust > 0.0
+  {
+    0.0
+  }
+  else
+  {
+    IDLE_MOVEMENT
+  };
+
+  if player_input.fire()
+  {
+    actions[ActionIndex::Shooting as usize] = 1.0;
+  }
+
+  if player_input.brake()
+  {
+    actions[ActionIndex::Brake as usize] = 1.0;
+  }
+
+  actions
+}
+
+
+fn think(brain: &mut Brain,
+         sensations: &Vec<f32>,
+         player_input: &PlayerInput,
+) -> Vec<f32>
+{
+  let brain_out: Vec<_> = match *brain
+  {
+    Brain::Human => human_actions(player_input),
+    _ => brain.process_input(sensations),
+  };
+  check_brain_output(brain, &brain_out);
+  brain_out
+}
+
+
+fn check_brain_output(brain: &Brain, brain_out: &[f32])
+{
+  if brain_out.len() < MIN_ACTIONS
+  {
+    warn!("{} brain produced {} outputs, expected at least {}", brain.variant_name(), brain_out.len(), MIN_ACTIONS);
+  }
+}
+
+
+fn brain_process(brain_query: &mut Query<(Entity, &mut Brain)>,
                  children: &Children,
-                 sensations: &Vec<f32>
+                 sensations: &Vec<f32>,
+                 player_input: &PlayerInput,
 ) -> Vec<f32>
 {
   let mut outputs = vec![];
   for &child in children.iter()
   {
-    if let Ok(mut brain) = brain_query.get_mut(child)
+    if let Ok((_, mut brain)) = brain_query.get_mut(child)
     {
-      let brain_out: Vec<_> = brain.process_input(&sensations);
-      outputs.extend(brain_out);
+      outputs.extend(think(&mut brain, sensations, player_input));
       break;
     }
   }
@@ -203,73 +641,190 @@ fn brain_process(brain_query: &mut Query<&mut Brain>,
 }
 
 
+/// Runs every sensed brain on its sensations, all of them in parallel.
+/// Comparing genomes to share work between brains doesn't pay off, as nearly
+/// all of them differ after a few generations; instead every NEAT brain keeps
+/// its compiled network between frames. `sensed` maps brain entities to their
+/// agent and its sensations, the outputs are returned per agent.
+fn brain_process_batched(brain_query: &mut Query<(Entity, &mut Brain)>,
+                         sensed: HashMap<Entity, (Entity, Vec<f32>)>,
+                         player_input: &PlayerInput,
+) -> HashMap<Entity, Vec<f32>>
+{
+  let outputs = Mutex::new(HashMap::with_capacity(sensed.len()));
+  brain_query.par_iter_mut().for_each(|(brain_entity, mut brain)|
+  {
+    if let Some((agent_entity, sensations)) = sensed.get(&brain_entity)
+    {
+      let brain_out = think(&mut brain, sensations, player_input);
+      outputs.lock().unwrap().insert(*agent_entity, brain_out);
+    }
+  });
+  outputs.into_inner().unwrap()
+}
+
+
 fn update_agents(agents_query: Query<(Entity, &Children), With<Agent>>,
                  sensors_query: Query<&Sensor>,
-                 mut brain_query: Query<&mut Brain>,
-                 mut transform_velocity_q: Query<(&mut Transform, &mut Velocity), With<Agent>>,
+                 mut brain_query: Query<(Entity, &mut Brain)>,
+                 mut transform_velocity_q: AgentMotionQuery,
+                 health_query: Query<&Health, With<Agent>>,
+                 colliders_query: Query<(Entity, &GlobalTransform, &Collider)>,
                  vision_view: VisionView,
                  mut shooting_event_writer: EventWriter<ShootEvent>,
+                 player_input: PlayerInput,
+                 batched_inference: Res<BatchedInference>,
+                 mut observations: Option<ResMut<AgentObservations>>,
+                 mut recorder: Option<ResMut<ActionRecorder>>,
+                 frame_counter: Option<Res<ExportFrameCounter>>,
                  time: Res<Time>,
 )
 {
-  for (agent_entity, children) in agents_query.iter()
+  if let Some(observations) = observations.as_mut()
+  {
+    observations.0.clear();
+  }
+
+  let frame_id = frame_counter.map_or(0, |frame_counter| frame_counter.0);
+
+  let colliders: Vec<_> = colliders_query.iter()
+    .map(|(entity, transform, collider)| (entity, transform.translation(), collider.radius))
+    .collect();
+
+  let sense = |agent_entity: Entity, children: &Children, transform_velocity_q: &AgentMotionQuery|
   {
-    let sensations = collect_sensations(&sensors_query, &children, &vision_view);
+    let context = SensingContext
+    {
+      agent: agent_entity,
+      vision_view: &vision_view,
+      agent_state: transform_velocity_q.get(agent_entity)
+        .ok()
+        .map(|(transform, velocity, _, _)| (transform, velocity, health_query.get(agent_entity).ok())),
+      colliders: &colliders,
+    };
+    collect_sensations(&sensors_query, children, &context)
+  };
 
-    let brain_output = brain_process(&mut brain_query, &children, &sensations);
+  let mut act = |agent_entity: Entity, brain_output: &Vec<f32>, transform_velocity_q: &mut AgentMotionQuery|
+  {
+    if let Some(recorder) = recorder.as_mut()
+    {
+      // Incomplete outputs are never applied, so there is nothing to replay.
+      if brain_output.len() >= MIN_ACTIONS
+      {
+        let mut action = ExternalBrain::default().action;
+        for (recorded, &output) in action.iter_mut().zip(brain_output)
+        {
+          *recorded = output;
+        }
+        recorder.actions.push(RecordedAction { frame_id, agent: agent_entity.to_bits(), action });
+      }
+    }
 
-    if let Ok((mut transform, mut velocity)) = transform_velocity_q.get_mut(agent_entity)
+    if let Ok((mut transform, velocity, mut acceleration, locomotion)) = transform_velocity_q.get_mut(agent_entity)
     {
       update_agent_state(agent_entity,
                          &mut transform,
-                         &mut velocity,
-                         &brain_output,
+                         &velocity,
+                         &mut acceleration,
+                         &locomotion.copied().unwrap_or_default(),
+                         brain_output,
                          &mut shooting_event_writer,
                          &time);
     }
+  };
+
+  if batched_inference.0
+  {
+    let mut sensed = HashMap::new();
+    for (agent_entity, children) in agents_query.iter()
+    {
+      let brain_entity = children.iter().copied().find(|&child| brain_query.contains(child));
+      if let Some(brain_entity) = brain_entity
+      {
+        let sensations = sense(agent_entity, children, &transform_velocity_q);
+        if let Some(observations) = observations.as_mut()
+        {
+          observations.0.insert(agent_entity, sensations.clone());
+        }
+        sensed.insert(brain_entity, (agent_entity, sensations));
+      }
+    }
+
+    // Act in query order rather than hash map order to stay deterministic.
+    let outputs = brain_process_batched(&mut brain_query, sensed, &player_input);
+    for (agent_entity, _) in agents_query.iter()
+    {
+      if let Some(brain_output) = outputs.get(&agent_entity)
+      {
+        act(agent_entity, brain_output, &mut transform_velocity_q);
+      }
+    }
+    return;
+  }
+
+  for (agent_entity, children) in agents_query.iter()
+  {
+    let sensations = sense(agent_entity, children, &transform_velocity_q);
+    if let Some(observations) = observations.as_mut()
+    {
+      observations.0.insert(agent_entity, sensations.clone());
+    }
+    let brain_output = brain_process(&mut brain_query, children, &sensations, &player_input);
+    act(agent_entity, &brain_output, &mut transform_velocity_q);
   }
 }
 
 
 fn update_agent_state(agent_entity: Entity,
                       transform: &mut Transform,
-                      velocity: &mut Velocity,
+                      velocity: &Velocity,
+                      acceleration: &mut Acceleration,
+                      locomotion: &Locomotion,
                       brain_output: &Vec<f32>,
                       shooting_event_writer: &mut EventWriter<ShootEvent>,
                       time: &Res<Time>,
 )
 {
+  // Brains under development may not produce a full action vector yet, in
+  // which case the agent does nothing this frame.
+  if brain_output.len() < MIN_ACTIONS
+  {
+    return;
+  }
+
   let mut rotation = 0.0;
-  let mut movement = 0.0;
+  let mut thrust = 0.0;
 
-  let do_rotate_right = brain_output[ActionIndex::Rotation as usize] < -0.1;
-  let do_rotate_left = brain_output[ActionIndex::Rotation as usize] > 0.1;
+  let do_rotate_right = brain_output[ActionIndex::Rotation as usize] < -ROTATION_THRESHOLD;
+  let do_rotate_left = brain_output[ActionIndex::Rotation as usize] > ROTATION_THRESHOLD;
 
   if do_rotate_right
   {
-    rotation = -ROTATION_SPEED * time.delta_seconds();
+    rotation = -locomotion.rotation_speed * time.delta_seconds();
   }
   else if do_rotate_left
   {
-    rotation = ROTATION_SPEED * time.delta_seconds();
+    rotation = locomotion.rotation_speed * time.delta_seconds();
   }
 
-  let do_move_forward = brain_output[ActionIndex::Movement as usize] < 0.4;
-  let do_move_backward = brain_output[ActionIndex::Movement as usize] > 0.5;
+  let do_move_forward = brain_output[ActionIndex::Movement as usize] < FORWARD_THRESHOLD;
+  let do_move_backward = brain_output[ActionIndex::Movement as usize] > BACKWARD_THRESHOLD;
 
   if do_move_backward
   {
-    movement = -SPEED;
+    thrust = -locomotion.thrust * REVERSE_THRUST_RATIO;
   }
   else if do_move_forward
   {
-    movement = SPEED;
+    thrust = locomotion.thrust;
   }
 
 
-  let do_shoot = brain_output[ActionIndex::Shooting as usize] > 0.95;
+  let do_shoot = brain_output[ActionIndex::Shooting as usize] > SHOOT_THRESHOLD;
   if do_shoot
   {
+    // The weapon cooldown in handle_shoot_events limits the actual fire rate.
     shooting_event_writer.send(ShootEvent::new(agent_entity));
   }
 
@@ -277,5 +832,210 @@ fn update_agent_state(agent_entity: Entity,
   // Ignores the Z-axis rotation applied below.
   transform.rotate_y(rotation);
 
-  velocity.value = transform.forward() * movement;
+  // Braking overrides thrust. Otherwise thrust accelerates the agent and its
+  // damping slows it down once it stops.
+  let do_brake = brain_output.get(ActionIndex::Brake as usize)
+    .map_or(false, |&brake| brake > BRAKE_THRESHOLD);
+  acceleration.value = if do_brake
+  {
+    -velocity.value * BRAKE_DECELERATION
+  }
+  else
+  {
+    transform.forward() * thrust
+  };
+}
+
+
+#[cfg(test)]
+mod tests
+{
+  use std::sync::Arc;
+
+  use bevy::render::render_resource::TextureFormat;
+  use gpu_copy::ImageWrapper;
+  use image::{DynamicImage, Rgba, RgbaImage};
+
+  use super::*;
+  use crate::input::InputBindings;
+  use crate::neat::{NeatBrain, NodeKind};
+  use crate::vision::{ViewParams, Vision, VisionSampling, VisionSnapshot, VisionTargets, DEFAULT_VISION_TARGET};
+
+  const VIEW_WIDTH: u32 = 2;
+
+  #[derive(Resource, Default)]
+  struct CollectedSensations(Vec<Vec<f32>>);
+
+  fn collect_agent_sensations(agents: Query<(Entity, &Children), With<Agent>>,
+                              sensors_query: Query<&Sensor>,
+                              vision_view: VisionView,
+                              mut collected: ResMut<CollectedSensations>,
+  )
+  {
+    for (agent, children) in agents.iter()
+    {
+      let context = SensingContext { agent, vision_view: &vision_view, agent_state: None, colliders: &[] };
+      collected.0.push(collect_sensations(&sensors_query, children, &context));
+    }
+  }
+
+  /// Atlas of one pixel high viewports, each filled with its vision's id.
+  fn vision_frame(num_visions: u32, frame_id: u64) -> Arc<ImageWrapper>
+  {
+    let atlas = RgbaImage::from_fn(VIEW_WIDTH * num_visions, 1, |x, _| Rgba([(x / VIEW_WIDTH) as u8; 4]));
+    Arc::new(ImageWrapper { img_buffer: DynamicImage::ImageRgba8(atlas), format: TextureFormat::Rgba8UnormSrgb, frame_id, render_frame: frame_id })
+  }
+
+  #[test]
+  fn sensation_layout_follows_vision_ids_across_frames()
+  {
+    let spawn_order: [isize; 4] = [2, 0, 3, 1];
+
+    let mut app = App::new();
+    let snapshot = [(DEFAULT_VISION_TARGET.to_string(), vec![vision_frame(spawn_order.len() as u32, 1)])];
+    app.insert_resource(VisionSnapshot(snapshot.into_iter().collect()))
+      .init_resource::<VisionTargets>()
+      .init_resource::<CollectedSensations>()
+      .add_systems(Update, collect_agent_sensations);
+
+    let agent = app.world.spawn(Agent).id();
+    for id in spawn_order
+    {
+      let sensor = app.world.spawn(Sensor::Vision(Vision
+      {
+        id,
+        visual_sensor: Some(ViewParams { x: id as u32 * VIEW_WIDTH, y: 0, width: VIEW_WIDTH, height: 1 }),
+        sampling: VisionSampling::Row(0),
+        ..default()
+      })).id();
+      app.world.entity_mut(agent).add_child(sensor);
+    }
+
+    app.update();
+    app.world.resource_mut::<VisionSnapshot>().0
+      .insert(DEFAULT_VISION_TARGET.to_string(), vec![vision_frame(spawn_order.len() as u32, 2)]);
+    app.update();
+
+    let expected: Vec<f32> = (0..spawn_order.len())
+      .flat_map(|id| vec![id as f32; VIEW_WIDTH as usize * 4])
+      .collect();
+    let collected = &app.world.resource::<CollectedSensations>().0;
+    assert_eq!(collected, &vec![expected.clone(), expected]);
+  }
+
+  const BATCH_AGENTS: usize = 128;
+  const BATCH_INPUTS: usize = 256;
+
+  /// A distinct NEAT genome per agent, plus a random brain with its own RNG
+  /// state, and their sensations.
+  fn distinct_brains() -> Vec<(Brain, Vec<f32>)>
+  {
+    let mut rng = StdRng::seed_from_u64(3);
+    let mut brains = vec![];
+    for agent in 0..BATCH_AGENTS
+    {
+      let brain = match agent
+      {
+        0 => Brain::Random(RandomBrain::seeded(5)),
+        _ => Brain::Neat(NeatBrain::minimal(BATCH_INPUTS, &mut rng)),
+      };
+      let sensations = (0..BATCH_INPUTS).map(|_| rng.gen_range(0.0..=1.0)).collect();
+      brains.push((brain, sensations));
+    }
+    brains
+  }
+
+  #[derive(Resource, Default)]
+  struct BatchedInferenceRun
+  {
+    sensed: HashMap<Entity, (Entity, Vec<f32>)>,
+    outputs: HashMap<Entity, Vec<f32>>,
+  }
+
+  fn run_brains_batched(mut brain_query: Query<(Entity, &mut Brain)>,
+                        player_input: PlayerInput,
+                        mut run: ResMut<BatchedInferenceRun>,
+  )
+  {
+    let sensed = std::mem::take(&mut run.sensed);
+    run.outputs = brain_process_batched(&mut brain_query, sensed, &player_input);
+  }
+
+  #[test]
+  fn loads_version_1_neat_brains_without_brake()
+  {
+    // Two inputs, three outputs and a hidden node at index 5, which moves
+    // behind the new brake output.
+    let v1_file = r#"{
+      "version": 1,
+      "brain": { "Neat": {
+        "num_inputs": 2,
+        "nodes": [
+          { "kind": "Input", "bias": 0.0 }, { "kind": "Input", "bias": 0.0 },
+          { "kind": "Output", "bias": 0.0 }, { "kind": "Output", "bias": 0.0 }, { "kind": "Output", "bias": 0.0 },
+          { "kind": "Hidden", "bias": 0.0 }
+        ],
+        "connections": [
+          { "from": 0, "to": 2, "weight": 1.0, "enabled": true },
+          { "from": 1, "to": 5, "weight": 0.5, "enabled": true },
+          { "from": 5, "to": 3, "weight": 1.0, "enabled": true }
+        ]
+      } }
+    }"#;
+    let path = std::env::temp_dir().join(format!("brain_v1_{}.json", std::process::id()));
+    std::fs::write(&path, v1_file).unwrap();
+    let loaded = Brain::load(&path);
+    let _ = std::fs::remove_file(&path);
+
+    let Ok(Brain::Neat(brain)) = loaded else { panic!("v1 NEAT brain didn't load: {loaded:?}") };
+    assert_eq!(brain.nodes[brain.output_nodes()].len(), NUM_ACTIONS);
+    assert_eq!(brain.nodes[6].kind, NodeKind::Hidden);
+
+    let outputs = brain.evaluate(&[1.0, 1.0]);
+    let expected = [1.0f32.tanh(), 0.5f32.tanh().tanh(), 0.0, 0.0];
+    for (output, expected) in outputs.iter().zip(expected)
+    {
+      assert!((output - expected).abs() < 1e-6, "{outputs:?} != {expected:?}");
+    }
+  }
+
+  #[test]
+  fn batched_inference_matches_sequential()
+  {
+    let mut app = App::new();
+    app.init_resource::<InputBindings>()
+      .init_resource::<ButtonInput<KeyCode>>()
+      .init_resource::<Gamepads>()
+      .init_resource::<Axis<GamepadAxis>>()
+      .init_resource::<ButtonInput<GamepadButton>>()
+      .init_resource::<BatchedInferenceRun>()
+      .add_systems(Update, run_brains_batched);
+
+    let mut expected = HashMap::new();
+    let mut sensed = HashMap::new();
+    for (brain, sensations) in distinct_brains()
+    {
+      let mut sequential = brain.clone();
+      let agent = app.world.spawn(Agent).id();
+      let brain_entity = app.world.spawn(brain).id();
+      expected.insert(agent, sequential.process_input(&sensations));
+      sensed.insert(brain_entity, (agent, sensations));
+    }
+    app.world.resource_mut::<BatchedInferenceRun>().sensed = sensed;
+
+    app.update();
+
+    assert_eq!(app.world.resource::<BatchedInferenceRun>().outputs, expected);
+  }
+
+  #[test]
+  fn neat_batches_like_single_inputs()
+  {
+    let mut brains = distinct_brains().into_iter().skip(1);
+    let (mut brain, _) = brains.next().unwrap();
+    let batch: Vec<_> = brains.map(|(_, sensations)| sensations).collect();
+
+    let single: Vec<_> = batch.iter().map(|sensations| brain.process_input(sensations)).collect();
+    assert_eq!(brain.process_batch(&batch), single);
+  }
 }
@@ -1,8 +1,9 @@
 use bevy::prelude::*;
-use image::{ImageBuffer, Rgba};
-use std::path::Path;
 
-use crate::vision::{Vision as VisionSensor, VisionView};
+use crate::collision_detection::Collider;
+use crate::health::Health;
+use crate::movement::Velocity;
+use crate::vision::{Vision as VisionSensor, ViewParams, VisionView};
 
 
 /// Sensors provide the limitations on what agents are able to interact with.
@@ -10,90 +11,200 @@ use crate::vision::{Vision as VisionSensor, VisionView};
 pub enum Sensor
 {
   Vision(VisionSensor),
+  SelfState(SelfStateSensor),
+  /// Casts `num_rays` rays evenly around the agent, starting at its heading,
+  /// and reports the distance to the nearest collider along each of them.
+  Proximity { range: f32, num_rays: usize },
+}
+
+
+/// Proprioception - lets an agent feel its own velocity, heading and health.
+#[derive(Debug, Clone)]
+pub struct SelfStateSensor
+{
+  pub max_speed: f32,
+  pub max_health: f32,
+}
+
+
+impl SelfStateSensor
+{
+  pub fn new(max_speed: f32, max_health: f32) -> Self
+  {
+    Self { max_speed, max_health }
+  }
+
+  /// Returns `[velocity x, velocity z, heading x, heading z, health]`, each
+  /// normalized into [-1, 1] (health into [0, 1]). Agents move in the XZ plane
+  /// so the Y components are left out.
+  pub fn read_state(&self, transform: &Transform, velocity: &Velocity, health: Option<&Health>) -> Vec<f32>
+  {
+    let velocity = velocity.value / self.max_speed.max(f32::EPSILON);
+    let heading = transform.rotation * Vec3::NEG_Z;
+    let health = health.map_or(0.0, |health| health.value / self.max_health.max(f32::EPSILON));
+
+    vec![
+      velocity.x.clamp(-1.0, 1.0),
+      velocity.z.clamp(-1.0, 1.0),
+      heading.x,
+      heading.z,
+      health.clamp(0.0, 1.0),
+    ]
+  }
 }
 
 
 #[derive(Component, Debug)]
 pub enum Environment
 {
-  VisibleEnvironment
+  VisibleEnvironment,
+  /// Colliders physically surrounding the agent.
+  Surroundings,
+  /// The agent's own body.
+  Body,
 }
 
 
-pub trait Sensing
+/// A collider as seen by the sensors: entity, world position and radius.
+pub type ColliderSnapshot = (Entity, Vec3, f32);
+
+
+/// Everything a sensor may need to perceive the world around its agent.
+pub struct SensingContext<'a>
 {
-  fn sense(&self, environment: Environment, vision_views: &VisionView) -> Option<Vec<f32>>;
+  pub agent: Entity,
+  pub vision_view: &'a VisionView<'a, 'a>,
+  pub agent_state: Option<(&'a Transform, &'a Velocity, Option<&'a Health>)>,
+  pub colliders: &'a [ColliderSnapshot],
 }
 
 
-fn save_image_to_disk(image: &ImageBuffer<Rgba<u8>, Vec<u8>>, path: &Path) -> Result<(), image::ImageError>
+impl Sensor
 {
-  // Get the image dimensions
-  let (width, height) = image.dimensions();
-
-  if image.len() == (width * height * 4) as usize
+  /// Environment this sensor perceives.
+  pub fn environment(&self) -> Environment
   {
-//    let data = image.data.deref();
-//
-//    // This function assumes the image is in RGBA8 format.
-//    // If it's in a different format, conversion will be needed.
-//    let image_buffer = ImageBuffer::<Rgba<u8>, _>::from_raw(width, height, data)
-//        .ok_or(image::ImageError::Decoding(image::error::DecodingError::new(image::error::ImageFormatHint::Unknown, "Failed to create image buffer from raw data")))?;
-
-    debug!("Address of image buffer: {:?}", image.as_ptr());
-    image.save(path)
+    match self
+    {
+      Sensor::Vision(_) => Environment::VisibleEnvironment,
+      Sensor::SelfState(_) => Environment::Body,
+      Sensor::Proximity { .. } => Environment::Surroundings,
+    }
   }
-  else
+}
+
+
+pub trait Sensing
+{
+  fn sense(&self, environment: Environment, context: &SensingContext) -> Option<Vec<f32>>;
+}
+
+
+impl Sensing for Sensor
+{
+  fn sense(&self, environment: Environment, context: &SensingContext) -> Option<Vec<f32>>
   {
-    info!("Image width: {:?}", width);
-    info!("Image height: {:?}", height);
-    info!("Image data length: {:?}", image.len());
-    Err(image::ImageError::Parameter(image::error::ParameterError::from_kind(
-        image::error::ParameterErrorKind::DimensionMismatch,
-    )))
+    match self
+    {
+      Sensor::Vision(vision) => vision.sense(environment, context),
+      Sensor::SelfState(self_state) => self_state.sense(environment, context),
+      Sensor::Proximity { range, num_rays } => sense_proximity(*range, *num_rays, environment, context),
+    }
   }
 }
 
 
-impl Sensing for VisionSensor
+impl Sensing for SelfStateSensor
 {
-  fn sense(&self, environment: Environment, vision_views: &VisionView) -> Option<Vec<f32>>
+  fn sense(&self, environment: Environment, context: &SensingContext) -> Option<Vec<f32>>
   {
-    let row_number = 25;
     match environment
     {
-      Environment::VisibleEnvironment =>
+      Environment::Body =>
       {
-        if let Some(ref view_params) = self.visual_sensor
+        let (transform, velocity, health) = context.agent_state?;
+        Some(self.read_state(transform, velocity, health))
+      },
+      _ => None,
+    }
+  }
+}
+
+
+/// Normalized distance to the nearest collider hit by each ray: 0.0 means the
+/// collider touches the agent, 1.0 means nothing within `range`.
+fn sense_proximity(range: f32, num_rays: usize, environment: Environment, context: &SensingContext) -> Option<Vec<f32>>
+{
+  let Environment::Surroundings = environment else { return None; };
+  let (transform, _, _) = context.agent_state?;
+
+  let origin = Vec3::new(transform.translation.x, 0.0, transform.translation.z);
+  let heading = transform.rotation * Vec3::NEG_Z;
+  let heading_angle = heading.z.atan2(heading.x);
+  let range = range.max(f32::EPSILON);
+
+  let readings = (0..num_rays).map(|ray|
+  {
+    let angle = heading_angle + ray as f32 * std::f32::consts::TAU / num_rays as f32;
+    let direction = Vec3::new(angle.cos(), 0.0, angle.sin());
+
+    let nearest = context.colliders.iter()
+      .filter(|(entity, _, _)| *entity != context.agent)
+      .filter_map(|(_, position, radius)|
+      {
+        let offset = Vec3::new(position.x, 0.0, position.z) - origin;
+        let along = offset.dot(direction);
+        let off_ray_sq = offset.length_squared() - along * along;
+        let radius_sq = radius * radius;
+
+        if off_ray_sq > radius_sq
         {
-          let (image, frame_id) = &vision_views.get_view(&view_params);
-          let filename = format!("/tmp/{}/ai_agent_{}.png", self.id, frame_id);
-          let path = Path::new(filename.as_str());
+          return None;
+        }
 
-//          match save_image_to_disk(&image, path)
-//          {
-//            Ok(_) => debug!("Image saved to disk"),
-//            Err(e) => error!("Error saving image to disk: {:?}", e),
-//          }
+        let hit = along - (radius_sq - off_ray_sq).sqrt();
+        if offset.length_squared() <= radius_sq
+        {
+          Some(0.0)
+        }
+        else if (0.0..=range).contains(&hit)
+        {
+          Some(hit)
+        }
+        else
+        {
+          None
+        }
+      })
+      .fold(range, f32::min);
+
+    nearest / range
+  }).collect();
 
-//            info!("image data: {:?}", image.data);
+  Some(readings)
+}
 
-//            image.texture_descriptor.label.as_ref().map(|label| info!("Label: {:?}", label));
-          let width = image.width() as usize;
-          let start = (row_number * width) as usize;
-          let end = start + width;
-          let region_is_valid = start < image.len() && end <= image.len();
 
-          if region_is_valid
-          {
-            let row_data = image.as_raw()[start..end].iter().map(|&b| b as f32).collect();
-            return Some(row_data);
-          }
-          else
+impl Sensing for VisionSensor
+{
+  fn sense(&self, environment: Environment, context: &SensingContext) -> Option<Vec<f32>>
+  {
+    match environment
+    {
+      Environment::VisibleEnvironment =>
+      {
+        if let Some(ref view_params) = self.visual_sensor
+        {
+          // Set `VisionDebugConfig::save_frames` to see what the agent sees.
+          let mut samples = sample_eye(self, view_params, context)?;
+          if let Some(ref right_eye) = self.right_sensor
           {
-//            println!("Invalid region for sensor: {:?}", self.visual_sensor);
+            samples.extend(sample_eye(self, right_eye, context)?);
           }
-          None
+
+          // Raw channel values are in [0, 255], normalized ones in [0, 1].
+          let scale = if self.normalize { 1.0 / 255.0 } else { 1.0 };
+          Some(samples.iter().map(|&b| b as f32 * scale).collect())
         }
         else
         {
@@ -101,8 +212,34 @@ impl Sensing for VisionSensor
           None
         }
       },
+      _ => None,
+    }
+  }
+}
+
+
+/// Samples what one eye of `vision` sees, stacking `vision.stack` frames.
+fn sample_eye(vision: &VisionSensor, view_params: &ViewParams, context: &SensingContext) -> Option<Vec<u8>>
+{
+  let frame = context.vision_view.frame(vision, view_params)?;
+  let latest = vision.sampling.sample(&frame);
+  if latest.is_empty()
+  {
+    return None;
+  }
+
+  // Older frames first, zero padded so the length never changes.
+  let mut samples = Vec::with_capacity(latest.len() * vision.stack.max(1));
+  for age in (1..vision.stack).rev()
+  {
+    match context.vision_view.frame_at(vision, view_params, age).map(|older| vision.sampling.sample(&older))
+    {
+      Some(older) if older.len() == latest.len() => samples.extend(older),
+      _ => samples.resize(samples.len() + latest.len(), 0),
     }
   }
+  samples.extend(latest);
+  Some(samples)
 }
 
 
@@ -1,4 +1,4 @@
-use std::ops::Range;
+use std::{ops::Range, time::Duration};
 
 use bevy::prelude::*;
 use rand::prelude::*;
@@ -7,21 +7,99 @@ use crate::{
     asset_loader::SceneAssets,
     collision_detection::{Collider, CollisionDamage},
     health::Health,
-    movement::{Acceleration, MovingObjectBundle, Velocity},
+    movement::{Acceleration, Damping, MovingObjectBundle, Velocity},
     schedule::InGameSet,
-    camera::VisibleRange
+    camera::VisibleRange,
+    despawn::despawn_dead_entities,
+    random::SimulationRng,
 };
 
 const VELOCITY_SCALAR: f32 = 5.0;
 const ACCELERATION_SCALAR: f32 = 1.0;
+/// Caps an asteroid's drift at roughly ACCELERATION_SCALAR / DAMPING.
+const DAMPING: f32 = 0.05;
 const SPAWN_TIME_SECONDS: f32 = 0.2;
+const SPAWN_RAMP_PER_SECOND: f32 = 0.0005;
+/// The spawn interval never shrinks below this, however long a game runs.
+const MIN_SPAWN_TIME_SECONDS: f32 = 0.05;
+const MAX_ASTEROIDS: usize = 60;
 const ROTATE_SPEED: f32 = 2.5;
 const RADIUS: f32 = 2.0;
 const HEALTH: f32 = 80.0;
 const COLLISION_DAMAGE: f32 = 35.0;
+const MODEL_SCALE: f32 = 0.5;
+/// Asteroids smaller than this don't split any further.
+const MIN_SPLIT_SIZE: f32 = 0.25;
+/// Angle in radians between a fragment's and its parent's direction.
+const SPLIT_ANGLE: f32 = 0.5;
 
+/// `size` scales the radius, model, health and collision damage of an asteroid,
+/// 1.0 being a medium sized one.
 #[derive(Component, Debug)]
-pub struct Asteroid;
+pub struct Asteroid {
+    pub size: f32,
+}
+
+/// Size class a new asteroid is spawned with.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AsteroidSize {
+    Small,
+    Medium,
+    Large,
+}
+
+impl AsteroidSize {
+    const ALL: [AsteroidSize; 3] = [AsteroidSize::Small, AsteroidSize::Medium, AsteroidSize::Large];
+
+    pub fn random<R: Rng>(rng: &mut R) -> Self {
+        *Self::ALL.choose(rng).unwrap()
+    }
+
+    /// Value stored in `Asteroid::size`.
+    pub fn factor(self) -> f32 {
+        match self {
+            AsteroidSize::Small => 0.5,
+            AsteroidSize::Medium => 1.0,
+            AsteroidSize::Large => 1.5,
+        }
+    }
+}
+
+/// Asteroids start spawning every `spawn_interval` seconds, and the interval
+/// shrinks by `ramp_per_sec` for every second of game time.
+#[derive(Resource, Debug, Clone)]
+pub struct Difficulty {
+    pub spawn_interval: f32,
+    pub ramp_per_sec: f32,
+}
+
+impl Default for Difficulty {
+    fn default() -> Self {
+        Self {
+            spawn_interval: SPAWN_TIME_SECONDS,
+            ramp_per_sec: SPAWN_RAMP_PER_SECOND,
+        }
+    }
+}
+
+impl Difficulty {
+    pub fn current_interval(&self, elapsed_seconds: f32) -> f32 {
+        (self.spawn_interval - self.ramp_per_sec * elapsed_seconds).max(MIN_SPAWN_TIME_SECONDS)
+    }
+}
+
+/// Upper bound on live asteroids, fragments included. Bounds the per frame
+/// collision checks during long runs.
+#[derive(Resource, Debug, Clone)]
+pub struct AsteroidConfig {
+    pub max_asteroids: usize,
+}
+
+impl Default for AsteroidConfig {
+    fn default() -> Self {
+        Self { max_asteroids: MAX_ASTEROIDS }
+    }
+}
 
 #[derive(Resource, Debug)]
 pub struct SpawnTimer {
@@ -34,13 +112,21 @@ impl Plugin for AsteroidPlugin
 {
   fn build(&self, app: &mut App)
   {
-    app.insert_resource(SpawnTimer
+    app.init_resource::<Difficulty>()
+    .init_resource::<AsteroidConfig>()
+    .insert_resource(SpawnTimer
     {
-      timer: Timer::from_seconds(SPAWN_TIME_SECONDS, TimerMode::Repeating),
+      timer: Timer::from_seconds(SPAWN_TIME_SECONDS, TimerMode::Once),
     })
     .add_systems(
       Update,
       (spawn_asteroid, rotate_asteroids).in_set(InGameSet::EntityUpdates),
+    )
+    .add_systems(
+      Update,
+      split_destroyed_asteroids
+        .before(despawn_dead_entities)
+        .in_set(InGameSet::DespawnEntities),
     );
   }
 }
@@ -72,6 +158,10 @@ fn spawn_asteroid(
   time: Res<Time>,
   scene_assets: Res<SceneAssets>,
   visible_range: Res<VisibleRange>,
+  difficulty: Res<Difficulty>,
+  asteroid_config: Res<AsteroidConfig>,
+  asteroids: Query<(), With<Asteroid>>,
+  mut rng: ResMut<SimulationRng>,
 )
 {
   spawn_timer.timer.tick(time.delta());
@@ -79,10 +169,18 @@ fn spawn_asteroid(
       return;
   }
 
+  let interval = difficulty.current_interval(time.elapsed_seconds());
+  spawn_timer.timer.set_duration(Duration::from_secs_f32(interval));
+  spawn_timer.timer.reset();
+
+  if asteroids.iter().count() >= asteroid_config.max_asteroids {
+      return;
+  }
+
   let (x_range, z_range) = (visible_range.x_range.clone(), visible_range.z_range.clone());
   debug!("x range: {:?}, z range: {:?}", x_range, z_range);
 
-  let mut rng = rand::thread_rng();
+  let rng = rng.as_mut();
 
   let spawn_edge = rng.gen_bool(0.5); // true for X edge, false for Z edge
 
@@ -110,26 +208,83 @@ fn spawn_asteroid(
   let velocity = make_velocity_toward_screen(&x_range, &z_range, translation);
   let acceleration = random_unit_vector() * ACCELERATION_SCALAR;
 
+  let size = AsteroidSize::random(rng).factor();
+
+  spawn_sized_asteroid(&mut commands, &scene_assets, translation, velocity, acceleration, size);
+}
+
+
+fn spawn_sized_asteroid(commands: &mut Commands,
+                        scene_assets: &SceneAssets,
+                        translation: Vec3,
+                        velocity: Vec3,
+                        acceleration: Vec3,
+                        size: f32,
+)
+{
   commands.spawn((
     MovingObjectBundle {
       acceleration: Acceleration::new(acceleration),
       velocity: Velocity::new(velocity),
-      collider: Collider::new(RADIUS),
+      collider: Collider::new(RADIUS * size),
       model: SceneBundle
       {
         scene: scene_assets.asteroid.clone(),
         transform: Transform::from_translation(translation)
-                             .with_scale(Vec3::splat(0.5)),
+                             .with_scale(Vec3::splat(MODEL_SCALE * size)),
         ..default()
       },
     },
-    Asteroid,
-    Health::new(HEALTH),
-    CollisionDamage::new(COLLISION_DAMAGE),
+    Asteroid { size },
+    Damping(DAMPING),
+    Health::new(HEALTH * size),
+    CollisionDamage::new(COLLISION_DAMAGE * size),
   ));
 }
 
 
+/// Replaces every destroyed asteroid that is big enough with two fragments of
+/// half its size, flying off to either side of the parent's direction. Once
+/// the asteroid cap is reached destroyed asteroids no longer split.
+fn split_destroyed_asteroids(
+  mut commands: Commands,
+  query: Query<(&Asteroid, &Health, &Transform, &Velocity, &Acceleration)>,
+  scene_assets: Res<SceneAssets>,
+  asteroid_config: Res<AsteroidConfig>,
+)
+{
+  let mut live_asteroids = query.iter().filter(|(_, health, ..)| health.value > 0.0).count();
+
+  for (asteroid, health, transform, velocity, acceleration) in query.iter()
+  {
+    if health.value > 0.0 || asteroid.size / 2.0 < MIN_SPLIT_SIZE
+    {
+      continue;
+    }
+
+    if live_asteroids + 2 > asteroid_config.max_asteroids
+    {
+      continue;
+    }
+    live_asteroids += 2;
+
+    for angle in [SPLIT_ANGLE, -SPLIT_ANGLE]
+    {
+      let deflection = Quat::from_rotation_y(angle);
+      let direction = deflection * velocity.value.normalize_or_zero();
+      let offset = direction * RADIUS * asteroid.size / 2.0;
+
+      spawn_sized_asteroid(&mut commands,
+                           &scene_assets,
+                           transform.translation + offset,
+                           deflection * velocity.value,
+                           deflection * acceleration.value,
+                           asteroid.size / 2.0);
+    }
+  }
+}
+
+
 fn rotate_asteroids(mut query: Query<&mut Transform, With<Asteroid>>, time: Res<Time>)
 {
   for mut transform in query.iter_mut()
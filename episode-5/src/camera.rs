@@ -1,14 +1,66 @@
 use std::ops::Range;
 
-use bevy::{prelude::*, window::WindowResized};
+use bevy::{
+  input::mouse::{MouseScrollUnit, MouseWheel},
+  prelude::*,
+  render::camera::RenderTarget,
+  window::{PrimaryWindow, WindowRef, WindowResized},
+};
+use bevy_mod_picking::prelude::PickSelection;
 
+use crate::{
+  ai_agent::Agent,
+  collision_detection::{CollisionDamage, CollisionEvent},
+  event_handler::ExplosionEvent,
+  fitness::Fitness,
+};
+
+/// Default height of the main camera above the playfield.
 pub const CAMERA_DISTANCE: f32 = 120.0;
+const MIN_CAMERA_DISTANCE: f32 = 30.0;
+const MAX_CAMERA_DISTANCE: f32 = 400.0;
+const ZOOM_LINE_STEP: f32 = 10.0;
+const ZOOM_PIXEL_STEP: f32 = 0.5;
+const FALLBACK_EXTENT: f32 = 60.0;
+const FOLLOW_TOGGLE_KEY: KeyCode = KeyCode::KeyF;
+const DEFAULT_FOLLOW_LERP: f32 = 3.0;
+/// Trauma lost per second.
+const TRAUMA_DECAY: f32 = 1.5;
+/// Trauma added per point of collision damage right below the camera.
+const TRAUMA_PER_DAMAGE: f32 = 0.02;
+/// Trauma added per unit of explosion radius right below the camera.
+const TRAUMA_PER_EXPLOSION_RADIUS: f32 = 0.15;
+/// Largest offset of the camera in the XZ plane, at full trauma.
+const MAX_SHAKE_OFFSET: f32 = 1.5;
+const SHAKE_FREQUENCY: f32 = 25.0;
 
 #[derive(Component, Debug)]
 pub struct MainCamera;
 
 pub struct CameraPlugin;
 
+
+/// Shake of the `MainCamera` in [0, 1]. Collisions and explosions add to it
+/// depending on their damage and distance, and it decays over time. The
+/// offset grows with the square of the trauma, so small hits barely move the
+/// camera. Purely cosmetic, only applied with a window.
+#[derive(Component, Debug, Default)]
+pub struct CameraShake
+{
+  pub trauma: f32,
+  /// Offset applied last frame, taken back before the next one.
+  offset: Vec3,
+}
+
+
+impl CameraShake
+{
+  pub fn add_trauma(&mut self, trauma: f32)
+  {
+    self.trauma = (self.trauma + trauma).clamp(0.0, 1.0);
+  }
+}
+
 #[derive(Resource, Debug, Default)]
 pub struct VisibleRange
 {
@@ -17,63 +69,310 @@ pub struct VisibleRange
 }
 
 
+/// Follow mode of the main camera, toggled with F. While enabled the camera
+/// tracks `target` in the XZ plane at its usual height: the selected agent if
+/// there is one, otherwise the fittest agent. Once disabled it glides back to
+/// the center of the playfield. `lerp` is the fraction of the remaining
+/// distance covered per second.
+#[derive(Resource, Debug)]
+pub struct CameraFollow
+{
+  pub enabled: bool,
+  pub target: Option<Entity>,
+  pub lerp: f32,
+}
+
+
+impl Default for CameraFollow
+{
+  fn default() -> Self
+  {
+    Self
+    {
+      enabled: false,
+      target: None,
+      lerp: DEFAULT_FOLLOW_LERP,
+    }
+  }
+}
+
+
+/// Size of the playfield (along X and Z) used when the main camera has no
+/// render target to measure, e.g. when running headless without a window.
+#[derive(Resource, Debug)]
+pub struct FallbackExtent(pub Vec2);
+
+
+impl Default for FallbackExtent
+{
+  fn default() -> Self
+  {
+    Self(Vec2::splat(FALLBACK_EXTENT))
+  }
+}
+
+
+/// Height of the main camera, adjusted with the mouse wheel. The visible range
+/// is derived from it, so spawning and wrap-around follow the zoom level.
+#[derive(Resource, Debug)]
+pub struct CameraZoom
+{
+  pub distance: f32,
+  pub min_distance: f32,
+  pub max_distance: f32,
+}
+
+
+impl Default for CameraZoom
+{
+  fn default() -> Self
+  {
+    Self
+    {
+      distance: CAMERA_DISTANCE,
+      min_distance: MIN_CAMERA_DISTANCE,
+      max_distance: MAX_CAMERA_DISTANCE,
+    }
+  }
+}
+
+
 impl Plugin for CameraPlugin
 {
   fn build(&self, app: &mut App)
   {
     app.init_resource::<VisibleRange>()
+       .init_resource::<CameraFollow>()
+       .init_resource::<CameraZoom>()
+       .init_resource::<FallbackExtent>()
+       .add_systems(Update, (toggle_camera_follow, pick_follow_target, follow_target).chain())
+       .add_systems(
+         Update,
+         (add_camera_trauma, shake_camera)
+           .chain()
+           .after(follow_target)
+           .run_if(any_with_component::<PrimaryWindow>),
+       )
        .add_systems(Startup, spawn_camera)
        .add_event::<WindowResized>()
        .add_systems(PostStartup, update_visible_range)
-       .add_systems(PreUpdate, update_visible_range.run_if(on_event::<WindowResized>()));
+       .add_systems(PreUpdate, (
+         zoom_camera,
+         update_visible_range.run_if(on_event::<WindowResized>().or_else(resource_changed::<CameraZoom>)),
+       ).chain());
   }
 }
 
-fn spawn_camera(mut commands: Commands)
+fn spawn_camera(mut commands: Commands, camera_zoom: Res<CameraZoom>)
 {
   commands.spawn((
     Camera3dBundle
     {
-      transform: Transform::from_xyz(0.0, CAMERA_DISTANCE, 0.0)
+      transform: Transform::from_xyz(0.0, camera_zoom.distance, 0.0)
           .looking_at(Vec3::ZERO, Vec3::Z),
       ..default()
     },
     MainCamera,
+    CameraShake::default(),
+    // Corner cameras render after this one, the UI belongs to the full view.
+    IsDefaultUiCamera,
   ));
 }
 
 
-pub fn update_visible_range(window_query: Query<&Window>,
-                            camera_query: Query<&Projection, With<MainCamera>>,
+pub fn update_visible_range(camera_query: Query<(&Camera, &Projection), With<MainCamera>>,
+                            window_query: Query<&Window>,
+                            primary_window_query: Query<&Window, With<PrimaryWindow>>,
+                            images: Res<Assets<Image>>,
+                            camera_zoom: Res<CameraZoom>,
+                            fallback_extent: Res<FallbackExtent>,
                             mut visible_range: ResMut<VisibleRange>,
 )
 {
-  info!("Window has been resized!");
+  let extent = camera_query.get_single().ok().and_then(|(camera, projection)|
+  {
+    let Projection::Perspective(perspective_projection) = projection else { return None; };
+    let target_size = render_target_size(&camera.target, &window_query, &primary_window_query, &images)?;
+    if target_size.x <= 0.0 || target_size.y <= 0.0
+    {
+      return None;
+    }
 
-  for window in window_query.iter()
+    let visible_height = 2.0 * (camera_zoom.distance * (perspective_projection.fov / 2.0).tan());
+    let visible_width = visible_height * target_size.x / target_size.y;
+    Some(Vec2::new(visible_width, visible_height))
+  }).unwrap_or(fallback_extent.0);
+
+  // Calculate spawn ranges based on the visible area
+  visible_range.x_range = (-extent.x / 2.0) .. (extent.x / 2.0);
+  visible_range.z_range = (-extent.y / 2.0) .. (extent.y / 2.0);
+  info!("visible range: {:?}", visible_range);
+}
+
+
+fn render_target_size(target: &RenderTarget,
+                      window_query: &Query<&Window>,
+                      primary_window_query: &Query<&Window, With<PrimaryWindow>>,
+                      images: &Assets<Image>,
+) -> Option<Vec2>
+{
+  match target
   {
-    if window.title == "Vision" { continue; };
+    RenderTarget::Window(WindowRef::Primary) => primary_window_query.get_single().ok()
+      .map(|window| Vec2::new(window.width(), window.height())),
+    RenderTarget::Window(WindowRef::Entity(entity)) => window_query.get(*entity).ok()
+      .map(|window| Vec2::new(window.width(), window.height())),
+    RenderTarget::Image(handle) => images.get(handle).map(|image| image.size().as_vec2()),
+    RenderTarget::TextureView(_) => None,
+  }
+}
 
-    let aspect_ratio = window.width() as f32 / window.height() as f32;
 
-    if let Ok(projection) = camera_query.get_single()
-    {
-      if let Projection::Perspective(perspective_projection) = projection
-      {
-        let fov = perspective_projection.fov;
-        let visible_height = 2.0 * (CAMERA_DISTANCE * (fov / 2.0).tan());
-        let visible_width = visible_height * aspect_ratio;
-
-        // Calculate spawn ranges based on the visible area
-        visible_range.x_range = (-visible_width / 2.0) .. (visible_width / 2.0);
-        visible_range.z_range = (-visible_height / 2.0) .. (visible_height / 2.0);
-        info!("visible range: {:?}", visible_range);
-      }
-    }
-    else
+fn zoom_camera(mut scroll_events: EventReader<MouseWheel>,
+               mut camera_zoom: ResMut<CameraZoom>,
+               mut camera_query: Query<&mut Transform, With<MainCamera>>,
+)
+{
+  let scroll: f32 = scroll_events.read().map(|event| match event.unit
+  {
+    MouseScrollUnit::Line => event.y * ZOOM_LINE_STEP,
+    MouseScrollUnit::Pixel => event.y * ZOOM_PIXEL_STEP,
+  }).sum();
+
+  if scroll == 0.0
+  {
+    return;
+  }
+
+  let distance = (camera_zoom.distance - scroll).clamp(camera_zoom.min_distance, camera_zoom.max_distance);
+  if distance == camera_zoom.distance
+  {
+    return;
+  }
+  camera_zoom.distance = distance;
+
+  for mut transform in camera_query.iter_mut()
+  {
+    transform.translation.y = distance;
+  }
+}
+
+
+fn toggle_camera_follow(keyboard_input: Res<ButtonInput<KeyCode>>,
+                        mut camera_follow: ResMut<CameraFollow>,
+)
+{
+  if keyboard_input.just_pressed(FOLLOW_TOGGLE_KEY)
+  {
+    camera_follow.enabled = !camera_follow.enabled;
+    camera_follow.target = None;
+  }
+}
+
+
+fn pick_follow_target(mut camera_follow: ResMut<CameraFollow>,
+                      selected_agents: Query<Entity, (With<Agent>, With<PickSelection>)>,
+                      agents: Query<(Entity, &Fitness), With<Agent>>,
+)
+{
+  if !camera_follow.enabled
+  {
+    return;
+  }
+
+  let target = selected_agents.iter().next().or_else(||
+  {
+    let current = camera_follow.target.filter(|&target| agents.contains(target));
+    current.or_else(|| agents.iter()
+      .max_by(|(_, a), (_, b)| a.0.total_cmp(&b.0))
+      .map(|(entity, _)| entity))
+  });
+
+  if camera_follow.target != target
+  {
+    camera_follow.target = target;
+  }
+}
+
+
+fn follow_target(camera_follow: Res<CameraFollow>,
+                 targets: Query<&GlobalTransform>,
+                 mut camera_query: Query<&mut Transform, With<MainCamera>>,
+                 time: Res<Time>,
+)
+{
+  let Ok(mut camera_transform) = camera_query.get_single_mut() else { return; };
+
+  let focus = camera_follow.target
+    .filter(|_| camera_follow.enabled)
+    .and_then(|target| targets.get(target).ok())
+    .map_or(Vec3::ZERO, |transform| transform.translation());
+
+  let destination = Vec3::new(focus.x, camera_transform.translation.y, focus.z);
+  if camera_transform.translation.distance_squared(destination) < 1e-6
+  {
+    return;
+  }
+
+  let t = (camera_follow.lerp * time.delta_seconds()).min(1.0);
+  camera_transform.translation = camera_transform.translation.lerp(destination, t);
+}
+
+
+/// Scales trauma by how close `point` is to the camera: 1 right below it at
+/// the current zoom, falling off with the square of the distance.
+fn shake_attenuation(camera: &GlobalTransform, point: Vec3, camera_zoom: &CameraZoom) -> f32
+{
+  let distance = camera.translation().distance(point).max(camera_zoom.distance);
+  (camera_zoom.distance / distance).powi(2)
+}
+
+
+fn add_camera_trauma(mut collision_event_reader: EventReader<CollisionEvent>,
+                     mut explosion_event_reader: EventReader<ExplosionEvent>,
+                     collision_damage_query: Query<&CollisionDamage>,
+                     mut camera_query: Query<(&GlobalTransform, &mut CameraShake), With<MainCamera>>,
+                     camera_zoom: Res<CameraZoom>,
+)
+{
+  let Ok((camera, mut shake)) = camera_query.get_single_mut() else { return; };
+
+  for &CollisionEvent { b: collided_entity, point, .. } in collision_event_reader.read()
+  {
+    if let Ok(collision_damage) = collision_damage_query.get(collided_entity)
     {
-      visible_range.x_range = -30.0 .. 30.0;
-      visible_range.z_range = -30.0 .. 30.0;
+      shake.add_trauma(collision_damage.amount * TRAUMA_PER_DAMAGE * shake_attenuation(camera, point, &camera_zoom));
     }
   }
+
+  for &ExplosionEvent { translation, radius } in explosion_event_reader.read()
+  {
+    shake.add_trauma(radius * TRAUMA_PER_EXPLOSION_RADIUS * shake_attenuation(camera, translation, &camera_zoom));
+  }
+}
+
+
+/// Smooth noise in about [-1, 1], a sum of two sines that don't line up.
+fn shake_noise(t: f32, phase: f32) -> f32
+{
+  ((t + phase).sin() + 0.5 * (1.7 * t + 2.3 * phase).sin()) / 1.5
+}
+
+
+/// Moves the camera in the XZ plane by the current shake. Zoom only touches
+/// the height and follow mode lerps from wherever the camera is, so taking
+/// back last frame's offset leaves both undisturbed.
+fn shake_camera(mut camera_query: Query<(&mut Transform, &mut CameraShake), With<MainCamera>>,
+                time: Res<Time>,
+)
+{
+  let Ok((mut transform, mut shake)) = camera_query.get_single_mut() else { return; };
+
+  transform.translation -= shake.offset;
+  shake.trauma = (shake.trauma - TRAUMA_DECAY * time.delta_seconds()).max(0.0);
+
+  let t = time.elapsed_seconds() * SHAKE_FREQUENCY;
+  let strength = MAX_SHAKE_OFFSET * shake.trauma * shake.trauma;
+  shake.offset = Vec3::new(shake_noise(t, 0.0), 0.0, shake_noise(t, 10.0)) * strength;
+  transform.translation += shake.offset;
 }
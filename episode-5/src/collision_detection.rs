@@ -2,9 +2,9 @@ use bevy::{prelude::*, utils::HashMap};
 
 use crate::{
     asteroids::Asteroid,
-    health::Health,
+    health::{DeathEvent, Health, Invulnerable},
     schedule::InGameSet,
-    spaceship::Spaceship,
+    spaceship::{Spaceship, SpaceshipShield},
     event_handler::SpaceshipMissile
 };
 
@@ -23,6 +23,41 @@ impl Collider {
     }
 }
 
+/// Bit of every collision layer. Entities only collide when each one is a
+/// member of a layer the other filters for.
+pub mod layers {
+    pub const SPACESHIPS: u32 = 1 << 0;
+    pub const ASTEROIDS: u32 = 1 << 1;
+    pub const MISSILES: u32 = 1 << 2;
+    pub const ALL: u32 = u32::MAX;
+}
+
+/// Which layers an entity is on and which layers it collides with. Entities
+/// without one collide with everything.
+#[derive(Component, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CollisionLayer {
+    pub memberships: u32,
+    pub filters: u32,
+}
+
+impl CollisionLayer {
+    pub fn new(memberships: u32, filters: u32) -> Self {
+        Self { memberships, filters }
+    }
+
+    pub fn interacts_with(&self, other: &CollisionLayer) -> bool {
+        self.memberships & other.filters != 0 && other.memberships & self.filters != 0
+    }
+}
+
+/// Whether two entities with optional layers collide.
+fn layers_interact(a: Option<&CollisionLayer>, b: Option<&CollisionLayer>) -> bool {
+    match (a, b) {
+        (Some(a), Some(b)) => a.interacts_with(b),
+        _ => true,
+    }
+}
+
 #[derive(Component, Debug)]
 pub struct CollisionDamage {
     pub amount: f32,
@@ -34,18 +69,20 @@ impl CollisionDamage {
     }
 }
 
-#[derive(Event, Debug)]
+/// `a` collided with `b` at `point`, where their colliders touch. Events are
+/// sent from the point of view of each participant, so a collision between
+/// two kinds of entities produces one event per side, and `b`'s
+/// `CollisionDamage` is what `a` takes.
+#[derive(Event, Debug, Clone, Copy)]
 pub struct CollisionEvent {
-    pub entity: Entity,
-    pub collided_entity: Entity,
+    pub a: Entity,
+    pub b: Entity,
+    pub point: Vec3,
 }
 
 impl CollisionEvent {
-    pub fn new(entity: Entity, collided_entity: Entity) -> Self {
-        Self {
-            entity,
-            collided_entity,
-        }
+    pub fn new(a: Entity, b: Entity, point: Vec3) -> Self {
+        Self { a, b, point }
     }
 }
 
@@ -54,11 +91,11 @@ pub struct CollisionDetectionPlugin;
 impl Plugin for CollisionDetectionPlugin {
     fn build(&self, app: &mut App) {
         app.add_systems(
-            Update,
+            FixedUpdate,
             collision_detection.in_set(InGameSet::CollisionDetection),
         )
         .add_systems(
-            Update,
+            FixedUpdate,
             (
                 (
                     handle_collisions::<Asteroid>,
@@ -116,41 +153,78 @@ fn collision_detection(mut query: Query<(Entity, &GlobalTransform, &mut Collider
 
 fn handle_collisions<T: Component>(
     mut collision_event_writer: EventWriter<CollisionEvent>,
-    query: Query<(Entity, &Collider), With<T>>,
+    query: Query<(Entity, &GlobalTransform, &Collider), With<T>>,
+    layer_query: Query<&CollisionLayer>,
+    transform_query: Query<&GlobalTransform>,
 ) {
-    for (entity, collider) in query.iter() {
+    for (entity, transform, collider) in query.iter() {
         for &collided_entity in collider.colliding_entities.iter() {
             // Entity collided with another entity of the same type.
             if query.get(collided_entity).is_ok() {
                 continue;
             }
+            // Entities on layers that ignore each other pass through.
+            if !layers_interact(layer_query.get(entity).ok(), layer_query.get(collided_entity).ok()) {
+                continue;
+            }
             // Send collision event.
-            collision_event_writer.send(CollisionEvent::new(entity, collided_entity));
+            let Ok(collided_transform) = transform_query.get(collided_entity) else {
+                continue;
+            };
+            // The point on this entity's collider facing the other entity.
+            let direction = (collided_transform.translation() - transform.translation()).normalize_or_zero();
+            let point = transform.translation() + direction * collider.radius;
+
+            collision_event_writer.send(CollisionEvent::new(entity, collided_entity, point));
         }
     }
 }
 
 
-fn apply_collision_damage(
+pub fn apply_collision_damage(
     mut collision_event_reader: EventReader<CollisionEvent>,
-    mut health_query: Query<&mut Health>,
+    mut health_query: Query<(&mut Health, Option<&SpaceshipShield>, Option<&mut Invulnerable>)>,
     collision_damage_query: Query<&CollisionDamage>,
+    mut death_event_writer: EventWriter<DeathEvent>,
 )
 {
   for &CollisionEvent {
-      entity,
-      collided_entity,
+      a: entity,
+      b: collided_entity,
+      ..
   } in collision_event_reader.read()
   {
-    let Ok(mut health) = health_query.get_mut(entity) else {
+    let Ok((mut health, shield, invulnerable)) = health_query.get_mut(entity) else {
         continue;
     };
 
+    // Shielded entities don't take any damage.
+    if shield.is_some() {
+        continue;
+    }
+
     let Ok(collision_damage) = collision_damage_query.get(collided_entity) else {
         continue;
     };
 
+    // Recently hit entities skip the damage, the hit restarts their timer
+    // right away so further hits in the same step miss too.
+    if let Some(mut invulnerable) = invulnerable {
+        if invulnerable.is_active() {
+            continue;
+        }
+        invulnerable.trigger();
+    }
+
     // Apply any damage that should be dealt as a result of the collision.
+    let was_alive = health.value > 0.0;
     health.value -= collision_damage.amount;
+
+    if was_alive && health.value <= 0.0 {
+        death_event_writer.send(DeathEvent {
+            entity,
+            killed_by: Some(collided_entity),
+        });
+    }
   }
 }
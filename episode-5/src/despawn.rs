@@ -1,8 +1,32 @@
 use bevy::prelude::*;
 
-use crate::{health::Health, schedule::InGameSet, state::GameState};
+use crate::{camera::VisibleRange, health::Health, schedule::InGameSet, state::GameState};
 
-const DESPAWN_DISTANCE: f32 = 100.0;
+/// Despawn distance while the visible range is small or not known yet.
+pub const DESPAWN_DISTANCE: f32 = 100.0;
+/// How far beyond the corners of the visible range entities are despawned.
+const DESPAWN_MARGIN: f32 = 20.0;
+const DEFAULT_LIFETIME_SECONDS: f32 = 3.0;
+
+/// Despawns the entity once `timer` finishes, whatever happens to it before.
+#[derive(Component, Debug)]
+pub struct Lifetime {
+    pub timer: Timer,
+}
+
+impl Lifetime {
+    pub fn new(seconds: f32) -> Self {
+        Self {
+            timer: Timer::from_seconds(seconds, TimerMode::Once),
+        }
+    }
+}
+
+impl Default for Lifetime {
+    fn default() -> Self {
+        Self::new(DEFAULT_LIFETIME_SECONDS)
+    }
+}
 
 pub struct DespawnPlugin;
 
@@ -10,30 +34,63 @@ impl Plugin for DespawnPlugin {
     fn build(&self, app: &mut App) {
         app.add_systems(
             Update,
-            (despawn_far_away_entities, despawn_dead_entities).in_set(InGameSet::DespawnEntities),
+            (
+                despawn_far_away_entities,
+                despawn_dead_entities,
+                despawn_expired_entities,
+            )
+                .in_set(InGameSet::DespawnEntities),
         )
         .add_systems(OnEnter(GameState::GameOver), despawn_all_entities);
     }
 }
 
-fn despawn_far_away_entities(
+/// Distance from the origin beyond which entities are despawned. It grows
+/// with the visible range when the camera zooms out, so nothing on screen is
+/// despawned.
+pub fn despawn_distance(visible_range: &VisibleRange) -> f32 {
+    let corner = |range: &std::ops::Range<f32>| range.start.abs().max(range.end.abs());
+    let half_diagonal = Vec2::new(corner(&visible_range.x_range), corner(&visible_range.z_range)).length();
+    DESPAWN_DISTANCE.max(half_diagonal + DESPAWN_MARGIN)
+}
+
+/// Entity is far away from the camera's viewport.
+pub fn is_far_away(transform: &GlobalTransform, visible_range: &VisibleRange) -> bool {
+    transform.translation().distance(Vec3::ZERO) > despawn_distance(visible_range)
+}
+
+/// Entity doesn't have any health.
+pub fn is_dead(health: &Health) -> bool {
+    health.value <= 0.0
+}
+
+pub fn despawn_far_away_entities(
     mut commands: Commands,
     query: Query<(Entity, &GlobalTransform), With<Health>>,
+    visible_range: Res<VisibleRange>,
 ) {
     for (entity, transform) in query.iter() {
-        let distance = transform.translation().distance(Vec3::ZERO);
-
-        // Entity is far away from the camera's viewport.
-        if distance > DESPAWN_DISTANCE {
+        if is_far_away(transform, &visible_range) {
             commands.entity(entity).despawn_recursive();
         }
     }
 }
 
-fn despawn_dead_entities(mut commands: Commands, query: Query<(Entity, &Health)>) {
+pub fn despawn_dead_entities(mut commands: Commands, query: Query<(Entity, &Health)>) {
     for (entity, health) in query.iter() {
-        // Entity doesn't have any health.
-        if health.value <= 0.0 {
+        if is_dead(health) {
+            commands.entity(entity).despawn_recursive();
+        }
+    }
+}
+
+fn despawn_expired_entities(
+    mut commands: Commands,
+    mut query: Query<(Entity, &mut Lifetime)>,
+    time: Res<Time>,
+) {
+    for (entity, mut lifetime) in query.iter_mut() {
+        if lifetime.timer.tick(time.delta()).finished() {
             commands.entity(entity).despawn_recursive();
         }
     }
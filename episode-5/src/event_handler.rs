@@ -1,6 +1,7 @@
-use bevy::prelude::*;
+use bevy::{prelude::*, window::PrimaryWindow};
+use bevy_mod_picking::prelude::Pickable;
 
-use crate::{ai_agent::{Agent, ShootEvent}, asset_loader::SceneAssets, collision_detection::{Collider, CollisionDamage}, health::Health, movement::{Acceleration, MovingObjectBundle, Velocity}};
+use crate::{ai_agent::{Agent, ShootEvent}, asset_loader::SceneAssets, collision_detection::{apply_collision_damage, layers, Collider, CollisionDamage, CollisionLayer}, despawn::Lifetime, health::{DeathEvent, Health}, movement::{Acceleration, MovingObjectBundle, Velocity}, schedule::InGameSet, state::GameState};
 
 
 pub struct EventHandlerPlugin;
@@ -8,26 +9,164 @@ pub struct EventHandlerPlugin;
 #[derive(Component, Debug)]
 pub struct SpaceshipMissile;
 
+
+/// Missiles hit anything except spaceships and other missiles, so a ship can
+/// neither shoot itself nor another ship of the fleet.
+pub fn missile_collision_layer() -> CollisionLayer
+{
+  CollisionLayer::new(layers::MISSILES, layers::ALL & !layers::SPACESHIPS & !layers::MISSILES)
+}
+
+
+/// Agent that fired a missile.
+#[derive(Component, Debug, Clone, Copy)]
+pub struct MissileOwner(pub Entity);
+
 const MISSILE_SPEED: f32 = 50.0;
 const MISSILE_FORWARD_SPAWN_SCALAR: f32 = 2.0;
 const MISSILE_RADIUS: f32 = 0.3;
 const MISSILE_HEALTH: f32 = 1.0;
 const MISSILE_COLLISION_DAMAGE: f32 = 5.0;
 const MISSILE_SCALE: Vec3 = Vec3::splat(0.3);
+const WEAPON_COOLDOWN_SECONDS: f32 = 0.5;
+const EXPLOSION_SECONDS: f32 = 0.4;
+/// Final radius of an explosion relative to the radius of what exploded.
+const EXPLOSION_GROWTH: f32 = 3.0;
+/// Radius of entities exploding without a collider.
+const DEFAULT_EXPLOSION_RADIUS: f32 = 1.0;
+
+
+/// Sent for every entity that died, where and how big it was.
+#[derive(Event, Debug, Clone, Copy)]
+pub struct ExplosionEvent
+{
+  pub translation: Vec3,
+  pub radius: f32,
+}
+
+
+/// Purely cosmetic expanding sphere left behind by a dead entity.
+#[derive(Component, Debug)]
+struct Explosion
+{
+  timer: Timer,
+  radius: f32,
+}
+
+
+#[derive(Resource)]
+struct ExplosionAssets
+{
+  mesh: Handle<Mesh>,
+  material: Handle<StandardMaterial>,
+}
+
+
+impl FromWorld for ExplosionAssets
+{
+  fn from_world(world: &mut World) -> Self
+  {
+    let mesh = world.resource_mut::<Assets<Mesh>>().add(Sphere::new(1.0));
+    let material = world.resource_mut::<Assets<StandardMaterial>>().add(StandardMaterial
+    {
+      base_color: Color::rgba(1.0, 0.6, 0.2, 0.5),
+      alpha_mode: AlphaMode::Blend,
+      unlit: true,
+      ..default()
+    });
+    Self { mesh, material }
+  }
+}
+
+
+/// Minimum time between two shots of the same entity. Missiles are only
+/// spawned once the timer has finished.
+#[derive(Component, Debug)]
+pub struct WeaponCooldown
+{
+  pub timer: Timer,
+}
+
+
+impl Default for WeaponCooldown
+{
+  fn default() -> Self
+  {
+    Self::new(WEAPON_COOLDOWN_SECONDS)
+  }
+}
+
+
+impl WeaponCooldown
+{
+  pub fn new(seconds: f32) -> Self
+  {
+    let mut timer = Timer::from_seconds(seconds, TimerMode::Once);
+    // Start out ready to fire.
+    timer.tick(timer.duration());
+    Self { timer }
+  }
+
+  /// Cooldown allowing at most `shots_per_second` shots.
+  pub fn from_fire_rate(shots_per_second: f32) -> Self
+  {
+    Self::new(1.0 / shots_per_second.max(f32::EPSILON))
+  }
+
+  /// Restarts the cooldown and returns true if the weapon was ready.
+  pub fn try_fire(&mut self) -> bool
+  {
+    if !self.timer.finished()
+    {
+      return false;
+    }
+    self.timer.reset();
+    true
+  }
+}
 
 
 impl Plugin for EventHandlerPlugin
 {
   fn build(&self, app: &mut App)
   {
-    app.add_systems(Update, handle_shoot_events);
+    app.add_systems(
+      Update,
+      (tick_weapon_cooldowns, handle_shoot_events)
+        .chain()
+        .run_if(in_state(GameState::InGame)),
+    )
+    .add_systems(
+      FixedUpdate,
+      explode_dead_entities
+        .after(apply_collision_damage)
+        .in_set(InGameSet::EntityUpdates),
+    )
+    // Explosions are only there for human observers.
+    .add_systems(
+      Update,
+      (spawn_explosions.run_if(any_with_component::<PrimaryWindow>), animate_explosions)
+        .chain()
+        .in_set(InGameSet::EntityUpdates),
+    )
+    .init_resource::<ExplosionAssets>()
+    .add_event::<ExplosionEvent>();
   }
 }
 
 
 
+pub fn tick_weapon_cooldowns(mut query: Query<&mut WeaponCooldown>, time: Res<Time>)
+{
+  for mut cooldown in query.iter_mut()
+  {
+    cooldown.timer.tick(time.delta());
+  }
+}
+
+
 fn handle_shoot_events(mut commands: Commands,
-                       query: Query<&Transform, With<Agent>>,
+                       mut query: Query<(&Transform, Option<&mut WeaponCooldown>), With<Agent>>,
                        scene_assets: Res<SceneAssets>,
                        mut shooting_event_reader: EventReader<ShootEvent>,
 )
@@ -36,8 +175,16 @@ fn handle_shoot_events(mut commands: Commands,
     entity
   } in shooting_event_reader.read()
   {
-    if let Ok(transform) = query.get(entity)
+    if let Ok((transform, cooldown)) = query.get_mut(entity)
     {
+      if let Some(mut cooldown) = cooldown
+      {
+        if !cooldown.try_fire()
+        {
+          continue;
+        }
+      }
+
       commands.spawn((
         MovingObjectBundle
         {
@@ -53,9 +200,74 @@ fn handle_shoot_events(mut commands: Commands,
           },
         },
         SpaceshipMissile,
+        MissileOwner(entity),
+        missile_collision_layer(),
+        Lifetime::default(),
         Health::new(MISSILE_HEALTH),
         CollisionDamage::new(MISSILE_COLLISION_DAMAGE),
       ));
     }
   }
 }
+
+
+fn explode_dead_entities(mut death_event_reader: EventReader<DeathEvent>,
+                         query: Query<(&Transform, Option<&Collider>)>,
+                         mut explosion_event_writer: EventWriter<ExplosionEvent>,
+)
+{
+  for &DeathEvent { entity, .. } in death_event_reader.read()
+  {
+    if let Ok((transform, collider)) = query.get(entity)
+    {
+      explosion_event_writer.send(ExplosionEvent
+      {
+        translation: transform.translation,
+        radius: collider.map_or(DEFAULT_EXPLOSION_RADIUS, |collider| collider.radius),
+      });
+    }
+  }
+}
+
+
+fn spawn_explosions(mut commands: Commands,
+                    mut explosion_event_reader: EventReader<ExplosionEvent>,
+                    explosion_assets: Res<ExplosionAssets>,
+)
+{
+  for &ExplosionEvent { translation, radius } in explosion_event_reader.read()
+  {
+    // Not pickable, which also keeps it out of the vision layer.
+    commands.spawn((
+      PbrBundle
+      {
+        mesh: explosion_assets.mesh.clone(),
+        material: explosion_assets.material.clone(),
+        transform: Transform::from_translation(translation).with_scale(Vec3::splat(radius)),
+        ..default()
+      },
+      Explosion { timer: Timer::from_seconds(EXPLOSION_SECONDS, TimerMode::Once), radius },
+      Pickable::IGNORE,
+    ));
+  }
+}
+
+
+fn animate_explosions(mut commands: Commands,
+                      mut query: Query<(Entity, &mut Explosion, &mut Transform)>,
+                      time: Res<Time>,
+)
+{
+  for (entity, mut explosion, mut transform) in query.iter_mut()
+  {
+    explosion.timer.tick(time.delta());
+    if explosion.timer.finished()
+    {
+      commands.entity(entity).despawn_recursive();
+      continue;
+    }
+
+    let growth = 1.0 + (EXPLOSION_GROWTH - 1.0) * explosion.timer.fraction();
+    transform.scale = Vec3::splat(explosion.radius * growth);
+  }
+}
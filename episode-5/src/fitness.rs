@@ -0,0 +1,105 @@
+use bevy::prelude::*;
+
+use crate::{
+  ai_agent::Agent,
+  asteroids::Asteroid,
+  collision_detection::{apply_collision_damage, CollisionDamage, CollisionEvent},
+  event_handler::MissileOwner,
+  health::DeathEvent,
+  schedule::InGameSet,
+  score::asteroid_points,
+  spaceship::SpaceshipShield,
+};
+
+
+/// Reward for every physics step an agent stays alive.
+const SURVIVAL_REWARD: f32 = 1.0;
+/// Reward per score point earned by destroying an asteroid with a missile.
+const SCORE_POINT_REWARD: f32 = 5.0;
+/// Penalty per point of collision damage an agent takes.
+const DAMAGE_PENALTY: f32 = 2.0;
+
+
+/// How well an agent is doing, used to select parents for the next generation.
+#[derive(Component, Debug, Default, Clone, Copy)]
+pub struct Fitness(pub f32);
+
+
+/// Highest fitness any agent has reached so far.
+#[derive(Resource, Debug, Default, Clone, Copy)]
+pub struct BestFitness(pub f32);
+
+
+pub struct FitnessPlugin;
+
+
+impl Plugin for FitnessPlugin
+{
+  fn build(&self, app: &mut App)
+  {
+    app.init_resource::<BestFitness>()
+      .add_systems(
+        FixedUpdate,
+        (reward_survival, reward_collisions, update_best_fitness)
+          .chain()
+          .after(apply_collision_damage)
+          .in_set(InGameSet::EntityUpdates),
+      );
+  }
+}
+
+
+fn reward_survival(mut agents: Query<&mut Fitness, With<Agent>>)
+{
+  for mut fitness in agents.iter_mut()
+  {
+    fitness.0 += SURVIVAL_REWARD;
+  }
+}
+
+
+/// Credits the shooter of a missile that destroyed an asteroid and penalizes
+/// agents for the damage they take. Runs after the collision damage has been
+/// applied, so the deaths it caused have been reported.
+fn reward_collisions(mut collision_event_reader: EventReader<CollisionEvent>,
+                     mut death_event_reader: EventReader<DeathEvent>,
+                     mut agents: Query<(&mut Fitness, Option<&SpaceshipShield>), With<Agent>>,
+                     asteroids: Query<&Asteroid>,
+                     missiles: Query<&MissileOwner>,
+                     collision_damage_query: Query<&CollisionDamage>,
+)
+{
+  for &DeathEvent { entity, killed_by } in death_event_reader.read()
+  {
+    let Some(killer) = killed_by else { continue; };
+    let Ok(asteroid) = asteroids.get(entity) else { continue; };
+
+    if let Ok(&MissileOwner(owner)) = missiles.get(killer)
+    {
+      if let Ok((mut fitness, _)) = agents.get_mut(owner)
+      {
+        fitness.0 += asteroid_points(asteroid) as f32 * SCORE_POINT_REWARD;
+      }
+    }
+  }
+
+  for &CollisionEvent { a: entity, b: collided_entity, .. } in collision_event_reader.read()
+  {
+    // Shielded agents take no damage, so they aren't penalized either.
+    if let (Ok((mut fitness, None)), Ok(collision_damage)) = (agents.get_mut(entity), collision_damage_query.get(collided_entity))
+    {
+      fitness.0 -= collision_damage.amount * DAMAGE_PENALTY;
+    }
+  }
+}
+
+
+fn update_best_fitness(agents: Query<&Fitness, With<Agent>>,
+                       mut best_fitness: ResMut<BestFitness>,
+)
+{
+  for fitness in agents.iter()
+  {
+    best_fitness.0 = best_fitness.0.max(fitness.0);
+  }
+}
@@ -1,12 +1,114 @@
 use bevy::prelude::*;
 
+use crate::{collision_detection::apply_collision_damage, schedule::InGameSet};
+
 #[derive(Component, Debug)]
 pub struct Health {
     pub value: f32,
+    pub max: f32,
 }
 
 impl Health {
     pub fn new(value: f32) -> Self {
-        Self { value }
+        Self { value, max: value }
+    }
+}
+
+/// Sent once when an entity's health drops to zero, before it is despawned.
+/// `killed_by` is the entity that dealt the final blow (e.g. the missile or
+/// asteroid it collided with), if known.
+#[derive(Event, Debug, Clone, Copy)]
+pub struct DeathEvent {
+    pub entity: Entity,
+    pub killed_by: Option<Entity>,
+}
+
+/// Regenerates `rate` health per second, up to `Health::max`, once the entity
+/// has gone `delay` seconds without losing any health.
+#[derive(Component, Debug)]
+pub struct HealthRegen {
+    pub rate: f32,
+    pub delay: f32,
+    since_damage: f32,
+    last_value: Option<f32>,
+}
+
+impl HealthRegen {
+    pub fn new(rate: f32, delay: f32) -> Self {
+        Self {
+            rate,
+            delay,
+            since_damage: 0.0,
+            last_value: None,
+        }
+    }
+}
+
+/// Keeps collisions from dealing damage for `timer` after every hit, so a
+/// long overlap or a burst of hits can't drain all health at once. The timer
+/// starts finished, i.e. the entity is vulnerable until it is first hit.
+#[derive(Component, Debug)]
+pub struct Invulnerable {
+    pub timer: Timer,
+}
+
+impl Invulnerable {
+    pub fn new(seconds: f32) -> Self {
+        let mut timer = Timer::from_seconds(seconds, TimerMode::Once);
+        timer.tick(timer.duration());
+        Self { timer }
+    }
+
+    pub fn is_active(&self) -> bool {
+        !self.timer.finished()
+    }
+
+    /// Restarts the invulnerability after a hit.
+    pub fn trigger(&mut self) {
+        self.timer.reset();
+    }
+}
+
+pub struct HealthPlugin;
+
+impl Plugin for HealthPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_event::<DeathEvent>()
+            .add_systems(
+                FixedUpdate,
+                regenerate_health
+                    .after(apply_collision_damage)
+                    .in_set(InGameSet::EntityUpdates),
+            )
+            .add_systems(
+                FixedUpdate,
+                tick_invulnerability
+                    .before(apply_collision_damage)
+                    .in_set(InGameSet::EntityUpdates),
+            );
+    }
+}
+
+fn tick_invulnerability(mut query: Query<&mut Invulnerable>, time: Res<Time>) {
+    for mut invulnerable in query.iter_mut() {
+        invulnerable.timer.tick(time.delta());
+    }
+}
+
+fn regenerate_health(mut query: Query<(&mut Health, &mut HealthRegen)>, time: Res<Time>) {
+    for (mut health, mut regen) in query.iter_mut() {
+        // Any drop since the last step counts as damage and restarts the delay.
+        if regen.last_value.is_some_and(|last_value| health.value < last_value) {
+            regen.since_damage = 0.0;
+        } else {
+            regen.since_damage += time.delta_seconds();
+        }
+
+        // The dead stay dead.
+        if health.value > 0.0 && health.value < health.max && regen.since_damage >= regen.delay {
+            health.value = (health.value + regen.rate * time.delta_seconds()).min(health.max);
+        }
+
+        regen.last_value = Some(health.value);
     }
 }
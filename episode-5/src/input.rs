@@ -0,0 +1,175 @@
+use std::marker::PhantomData;
+
+use bevy::{ecs::system::SystemParam, prelude::*};
+
+
+/// Keys driving the spaceship controls, plus their gamepad equivalents. The
+/// defaults are WASD to yaw and thrust, Left Shift/Left Ctrl to roll, Space to
+/// fire, X to brake and Tab for the shield. Human brains read the same
+/// bindings.
+#[derive(Resource, Debug, Clone)]
+pub struct InputBindings
+{
+  pub thrust_forward: KeyCode,
+  pub thrust_backward: KeyCode,
+  pub yaw_left: KeyCode,
+  pub yaw_right: KeyCode,
+  pub roll_left: KeyCode,
+  pub roll_right: KeyCode,
+  pub fire: KeyCode,
+  pub brake: KeyCode,
+  pub shield: KeyCode,
+  pub gamepad: GamepadBindings,
+}
+
+
+/// Gamepad side of the `InputBindings`. Sticks take over from the keys once
+/// they leave the dead zone.
+#[derive(Debug, Clone)]
+pub struct GamepadBindings
+{
+  pub thrust_axis: GamepadAxisType,
+  pub yaw_axis: GamepadAxisType,
+  pub roll_left: GamepadButtonType,
+  pub roll_right: GamepadButtonType,
+  pub fire: GamepadButtonType,
+  pub brake: GamepadButtonType,
+  pub shield: GamepadButtonType,
+  pub dead_zone: f32,
+}
+
+
+impl Default for InputBindings
+{
+  fn default() -> Self
+  {
+    Self
+    {
+      thrust_forward: KeyCode::KeyW,
+      thrust_backward: KeyCode::KeyS,
+      yaw_left: KeyCode::KeyA,
+      yaw_right: KeyCode::KeyD,
+      roll_left: KeyCode::ControlLeft,
+      roll_right: KeyCode::ShiftLeft,
+      fire: KeyCode::Space,
+      brake: KeyCode::KeyX,
+      shield: KeyCode::Tab,
+      gamepad: GamepadBindings::default(),
+    }
+  }
+}
+
+
+impl Default for GamepadBindings
+{
+  fn default() -> Self
+  {
+    Self
+    {
+      thrust_axis: GamepadAxisType::LeftStickY,
+      yaw_axis: GamepadAxisType::LeftStickX,
+      roll_left: GamepadButtonType::LeftTrigger,
+      roll_right: GamepadButtonType::RightTrigger,
+      fire: GamepadButtonType::South,
+      brake: GamepadButtonType::West,
+      shield: GamepadButtonType::East,
+      dead_zone: 0.15,
+    }
+  }
+}
+
+
+/// Player controls read through the `InputBindings`, from the keyboard and
+/// every connected gamepad.
+#[derive(SystemParam)]
+pub struct PlayerInput<'w, 's>
+{
+  bindings: Res<'w, InputBindings>,
+  keyboard: Res<'w, ButtonInput<KeyCode>>,
+  gamepads: Res<'w, Gamepads>,
+  gamepad_axes: Res<'w, Axis<GamepadAxis>>,
+  gamepad_buttons: Res<'w, ButtonInput<GamepadButton>>,
+  marker: PhantomData<&'s ()>,
+}
+
+
+impl<'w, 's> PlayerInput<'w, 's>
+{
+  /// Forward thrust in [-1, 1], negative reverses.
+  pub fn thrust(&self) -> f32
+  {
+    self.stick(self.bindings.gamepad.thrust_axis)
+      .unwrap_or_else(|| self.keys(self.bindings.thrust_backward, self.bindings.thrust_forward))
+  }
+
+  /// Turn rate in [-1, 1], positive turns left.
+  pub fn yaw(&self) -> f32
+  {
+    self.stick(self.bindings.gamepad.yaw_axis)
+      .map(|stick| -stick)
+      .unwrap_or_else(|| self.keys(self.bindings.yaw_right, self.bindings.yaw_left))
+  }
+
+  /// Roll rate in [-1, 1], positive rolls left.
+  pub fn roll(&self) -> f32
+  {
+    let gamepad = &self.bindings.gamepad;
+    if self.keyboard.pressed(self.bindings.roll_right) || self.button(gamepad.roll_right)
+    {
+      -1.0
+    }
+    else if self.keyboard.pressed(self.bindings.roll_left) || self.button(gamepad.roll_left)
+    {
+      1.0
+    }
+    else
+    {
+      0.0
+    }
+  }
+
+  pub fn fire(&self) -> bool
+  {
+    self.keyboard.pressed(self.bindings.fire) || self.button(self.bindings.gamepad.fire)
+  }
+
+  pub fn brake(&self) -> bool
+  {
+    self.keyboard.pressed(self.bindings.brake) || self.button(self.bindings.gamepad.brake)
+  }
+
+  pub fn shield(&self) -> bool
+  {
+    self.keyboard.pressed(self.bindings.shield) || self.button(self.bindings.gamepad.shield)
+  }
+
+  /// -1 while `negative` is held, otherwise 1 while `positive` is.
+  fn keys(&self, negative: KeyCode, positive: KeyCode) -> f32
+  {
+    if self.keyboard.pressed(negative)
+    {
+      -1.0
+    }
+    else if self.keyboard.pressed(positive)
+    {
+      1.0
+    }
+    else
+    {
+      0.0
+    }
+  }
+
+  /// First stick reading outside of the dead zone on any gamepad.
+  fn stick(&self, axis: GamepadAxisType) -> Option<f32>
+  {
+    self.gamepads.iter()
+      .filter_map(|gamepad| self.gamepad_axes.get(GamepadAxis::new(gamepad, axis)))
+      .find(|value| value.abs() > self.bindings.gamepad.dead_zone)
+  }
+
+  fn button(&self, button: GamepadButtonType) -> bool
+  {
+    self.gamepads.iter().any(|gamepad| self.gamepad_buttons.pressed(GamepadButton::new(gamepad, button)))
+  }
+}
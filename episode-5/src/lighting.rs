@@ -0,0 +1,50 @@
+use bevy::prelude::*;
+
+
+/// Scene appearance applied to `ClearColor` and `AmbientLight` whenever it
+/// changes, so what the vision cameras capture can be tuned at runtime.
+#[derive(Resource, Debug, Clone)]
+pub struct SceneLighting
+{
+  pub clear_color: Color,
+  pub ambient_color: Color,
+  pub ambient_brightness: f32,
+}
+
+
+impl Default for SceneLighting
+{
+  fn default() -> Self
+  {
+    Self
+    {
+      clear_color: Color::rgb(0.1, 0.0, 0.15),
+      ambient_color: Color::default(),
+      ambient_brightness: 800.0,
+    }
+  }
+}
+
+
+pub struct LightingPlugin;
+
+
+impl Plugin for LightingPlugin
+{
+  fn build(&self, app: &mut App)
+  {
+    app.init_resource::<SceneLighting>()
+       .add_systems(PreUpdate, apply_scene_lighting.run_if(resource_changed::<SceneLighting>));
+  }
+}
+
+
+fn apply_scene_lighting(scene_lighting: Res<SceneLighting>,
+                        mut clear_color: ResMut<ClearColor>,
+                        mut ambient_light: ResMut<AmbientLight>,
+)
+{
+  clear_color.0 = scene_lighting.clear_color;
+  ambient_light.color = scene_lighting.ambient_color;
+  ambient_light.brightness = scene_lighting.ambient_brightness;
+}
@@ -7,14 +7,25 @@ mod collision_detection;
 mod debug;
 mod despawn;
 mod event_handler;
+mod fitness;
 mod health;
+mod input;
+mod lighting;
+mod minimap;
 mod movement;
+mod neat;
+mod population;
+mod random;
 mod schedule;
+mod score;
+mod sim;
 mod spaceship;
 mod state;
 mod vision;
+mod vision_stream;
+mod world_snapshot;
 
-use bevy::prelude::*;
+use bevy::{prelude::*, window::ExitCondition, winit::WinitPlugin};
 
 use gpu_copy::GpuToCpuCpyPlugin;
 
@@ -28,39 +39,97 @@ use camera::CameraPlugin;
 use collision_detection::CollisionDetectionPlugin;
 use despawn::DespawnPlugin;
 use event_handler::EventHandlerPlugin;
+use fitness::FitnessPlugin;
+use health::HealthPlugin;
+use lighting::LightingPlugin;
+use minimap::MinimapPlugin;
 use movement::MovementPlugin;
+use population::PopulationPlugin;
+use random::RandomPlugin;
 use schedule::SchedulePlugin;
+use score::ScorePlugin;
+use sim::HeadlessSim;
 use spaceship::SpaceshipPlugin;
 use state::StatePlugin;
 use vision::VisionPlugin;
+use vision_stream::VisionStreamPlugin;
+use world_snapshot::WorldSnapshotPlugin;
 
 
-fn main()
+const DEFAULT_HEADLESS_STEPS: u64 = 1000;
+
+
+/// Builds the game. A headless app has no window, it is meant to be driven
+/// with `App::update`, see `HeadlessSim`.
+fn build_app(headless: bool) -> App
 {
-  App::new()
+  let default_plugins = if headless
+  {
+    DefaultPlugins.build()
+      .set(WindowPlugin { primary_window: None, exit_condition: ExitCondition::DontExit, ..default() })
+      .disable::<WinitPlugin>()
+  }
+  else
+  {
+    DefaultPlugins.build()
+  };
+
+  let mut app = App::new();
+  app
     // Bevy built-ins.
-    .insert_resource(ClearColor(Color::rgb(0.1, 0.0, 0.15)))
-    .insert_resource(AmbientLight {
-      color: Color::default(),
-      brightness: 800.0,
-    })
-    .add_plugins(DefaultPlugins)
+    .add_plugins(default_plugins)
     // User defined plugins.
+    .add_plugins(RandomPlugin)
+    .add_plugins(LightingPlugin)
     .add_plugins(AssetLoaderPlugin)
     .add_plugins(MovementPlugin)
     .add_plugins(SpaceshipPlugin)
     .add_plugins(AsteroidPlugin)
     .add_plugins(CameraPlugin)
+    .add_plugins(MinimapPlugin)
     .add_plugins(CollisionDetectionPlugin)
     .add_plugins(DespawnPlugin)
+    .add_plugins(HealthPlugin)
     .add_plugins(SchedulePlugin)
     .add_plugins(StatePlugin)
     .add_plugins(DefaultPickingPlugins)
-    .add_plugins(VisionPlugin)
+    .add_plugins(VisionPlugin::default())
     .add_plugins(AiAgentPlugin)
-    .add_plugins(GpuToCpuCpyPlugin)
-    .add_plugins(EventHandlerPlugin)
+    .add_plugins(FitnessPlugin)
+    .add_plugins(ScorePlugin)
+    .add_plugins(PopulationPlugin)
+    .add_plugins(GpuToCpuCpyPlugin::default())
+    .add_plugins(WorldSnapshotPlugin)
 //    .add_plugins(EditorPlugin::default())
     // .add_plugins(DebugPlugin)
-    .run();
+    .add_plugins(EventHandlerPlugin);
+
+  // Watch what the agents see, e.g. during headless training.
+  if let Ok(addr) = std::env::var("VISION_STREAM_ADDR")
+  {
+    app.add_plugins(VisionStreamPlugin::new(addr));
+  }
+  app
+}
+
+
+fn main()
+{
+  // `--headless [steps]` runs the given number of steps without a window.
+  let mut args = std::env::args().skip(1);
+  if args.next().as_deref() == Some("--headless")
+  {
+    let steps = args.next()
+      .map(|steps| steps.parse().expect("The number of steps must be a positive integer"))
+      .unwrap_or(DEFAULT_HEADLESS_STEPS);
+
+    let mut sim = HeadlessSim::new();
+    for _ in 0..steps
+    {
+      sim.step();
+    }
+    return;
+  }
+
+  build_app(false).run();
 }
@@ -0,0 +1,251 @@
+use bevy::{
+  prelude::*,
+  render::{
+    camera::{ClearColorConfig, RenderTarget, ScalingMode, Viewport},
+    view::RenderLayers,
+  },
+  window::{PrimaryWindow, WindowRef, WindowResized},
+};
+use bevy_mod_picking::prelude::Pickable;
+
+use crate::{
+  asteroids::Asteroid,
+  camera::{update_visible_range, VisibleRange},
+  collision_detection::Collider,
+  spaceship::Spaceship,
+};
+
+
+/// Layer of the minimap camera and its markers, so neither the main view nor
+/// the visions see them.
+pub const MINIMAP_LAYER: u8 = 2;
+/// Renders after the main camera and the vision preview.
+const MINIMAP_ORDER: isize = 2;
+/// Height the minimap camera looks down from, above everything on the field.
+const MINIMAP_ALTITUDE: f32 = 500.0;
+const DEFAULT_MINIMAP_WIDTH: f32 = 200.0;
+
+
+/// Logical width of the minimap in the bottom right corner of the window. Its
+/// height follows the aspect of the `VisibleRange`.
+#[derive(Resource, Debug)]
+pub struct MinimapConfig
+{
+  pub width: f32,
+}
+
+
+impl Default for MinimapConfig
+{
+  fn default() -> Self
+  {
+    Self { width: DEFAULT_MINIMAP_WIDTH }
+  }
+}
+
+
+#[derive(Component, Debug)]
+struct MinimapCamera;
+
+
+/// UI node drawn by the main camera behind the minimap, see `PreviewBackdrop`
+/// in the vision module.
+#[derive(Component, Debug)]
+struct MinimapBackdrop;
+
+
+#[derive(Resource)]
+struct MinimapMarkers
+{
+  mesh: Handle<Mesh>,
+  spaceship: Handle<StandardMaterial>,
+  asteroid: Handle<StandardMaterial>,
+}
+
+
+impl FromWorld for MinimapMarkers
+{
+  fn from_world(world: &mut World) -> Self
+  {
+    let mesh = world.resource_mut::<Assets<Mesh>>().add(Sphere::new(1.0));
+    let mut materials = world.resource_mut::<Assets<StandardMaterial>>();
+    let mut marker = |color| materials.add(StandardMaterial { base_color: color, unlit: true, ..default() });
+    Self
+    {
+      spaceship: marker(Color::LIME_GREEN),
+      asteroid: marker(Color::GRAY),
+      mesh,
+    }
+  }
+}
+
+
+/// Shows the whole playfield from above for human spectators, with ships and
+/// asteroids drawn as plain dots. Does nothing without a window.
+pub struct MinimapPlugin;
+
+
+impl Plugin for MinimapPlugin
+{
+  fn build(&self, app: &mut App)
+  {
+    app.init_resource::<MinimapConfig>()
+      .init_resource::<MinimapMarkers>()
+      .add_systems(PostStartup, spawn_minimap.after(update_visible_range))
+      .add_systems(
+        Update,
+        (
+          update_minimap.run_if(resource_changed::<VisibleRange>
+                                .or_else(resource_changed::<MinimapConfig>)
+                                .or_else(on_event::<WindowResized>())),
+          add_minimap_markers,
+        ).run_if(any_with_component::<MinimapCamera>),
+      );
+  }
+}
+
+
+/// Viewport, logical size and orthographic scaling of the minimap.
+fn minimap_layout(window: &Window, visible_range: &VisibleRange, width: f32) -> (Viewport, Vec2, ScalingMode)
+{
+  let extent = Vec2::new(visible_range.x_range.end - visible_range.x_range.start,
+                         visible_range.z_range.end - visible_range.z_range.start)
+    .max(Vec2::ONE);
+  let width = width.min(window.width());
+  let size = Vec2::new(width, width * extent.y / extent.x);
+
+  let window_size = UVec2::new(window.physical_width(), window.physical_height()).max(UVec2::ONE);
+  let physical_size = (size * window.scale_factor()).as_uvec2().clamp(UVec2::ONE, window_size);
+  let viewport = Viewport
+  {
+    physical_position: window_size - physical_size,
+    physical_size,
+    ..default()
+  };
+
+  (viewport, size, ScalingMode::Fixed { width: extent.x, height: extent.y })
+}
+
+
+/// Center of the `VisibleRange` on the playfield.
+fn minimap_center(visible_range: &VisibleRange) -> Vec3
+{
+  Vec3::new((visible_range.x_range.start + visible_range.x_range.end) / 2.0,
+            0.0,
+            (visible_range.z_range.start + visible_range.z_range.end) / 2.0)
+}
+
+
+fn spawn_minimap(mut commands: Commands,
+                 windows: Query<&Window, With<PrimaryWindow>>,
+                 visible_range: Res<VisibleRange>,
+                 config: Res<MinimapConfig>,
+)
+{
+  let Ok(window) = windows.get_single() else { return; };
+  let (viewport, size, scaling_mode) = minimap_layout(window, &visible_range, config.width);
+  let center = minimap_center(&visible_range);
+
+  commands.spawn((
+    Camera3dBundle
+    {
+      camera: Camera
+      {
+        order: MINIMAP_ORDER,
+        target: RenderTarget::Window(WindowRef::Primary),
+        viewport: Some(viewport),
+        clear_color: ClearColorConfig::None,
+        ..default()
+      },
+      projection: OrthographicProjection
+      {
+        scaling_mode,
+        far: 2.0 * MINIMAP_ALTITUDE,
+        ..default()
+      }.into(),
+      // Same orientation as the main camera, so the map isn't mirrored.
+      transform: Transform::from_translation(center + Vec3::Y * MINIMAP_ALTITUDE)
+        .looking_at(center, Vec3::Z),
+      ..default()
+    },
+    RenderLayers::layer(MINIMAP_LAYER),
+    MinimapCamera,
+  ));
+
+  commands.spawn((
+    NodeBundle
+    {
+      style: Style
+      {
+        position_type: PositionType::Absolute,
+        right: Val::Px(0.0),
+        bottom: Val::Px(0.0),
+        width: Val::Px(size.x),
+        height: Val::Px(size.y),
+        ..default()
+      },
+      background_color: Color::rgba(0.0, 0.0, 0.0, 0.8).into(),
+      ..default()
+    },
+    MinimapBackdrop,
+  ));
+}
+
+
+/// Keeps the minimap covering the `VisibleRange` and in its corner.
+fn update_minimap(mut cameras: Query<(&mut Camera, &mut Projection, &mut Transform), With<MinimapCamera>>,
+                  mut backdrops: Query<&mut Style, With<MinimapBackdrop>>,
+                  windows: Query<&Window, With<PrimaryWindow>>,
+                  visible_range: Res<VisibleRange>,
+                  config: Res<MinimapConfig>,
+)
+{
+  let Ok(window) = windows.get_single() else { return; };
+  let (viewport, size, scaling_mode) = minimap_layout(window, &visible_range, config.width);
+  let center = minimap_center(&visible_range);
+
+  for (mut camera, mut projection, mut transform) in cameras.iter_mut()
+  {
+    camera.viewport = Some(viewport.clone());
+    if let Projection::Orthographic(ref mut orthographic) = *projection
+    {
+      orthographic.scaling_mode = scaling_mode;
+    }
+    *transform = Transform::from_translation(center + Vec3::Y * MINIMAP_ALTITUDE)
+      .looking_at(center, Vec3::Z);
+  }
+
+  for mut style in backdrops.iter_mut()
+  {
+    style.width = Val::Px(size.x);
+    style.height = Val::Px(size.y);
+  }
+}
+
+
+/// Gives new ships and asteroids a dot of their collider's size on the
+/// minimap layer. Markers are children, so they go away with their entity.
+fn add_minimap_markers(mut commands: Commands,
+                       entities: Query<(Entity, &Transform, &Collider, Has<Spaceship>),
+                                       (Added<Collider>, Or<(With<Spaceship>, With<Asteroid>)>)>,
+                       markers: Res<MinimapMarkers>,
+)
+{
+  for (entity, transform, collider, is_spaceship) in entities.iter()
+  {
+    let material = if is_spaceship { &markers.spaceship } else { &markers.asteroid };
+    let marker = commands.spawn((
+      PbrBundle
+      {
+        mesh: markers.mesh.clone(),
+        material: material.clone(),
+        // Undo the model's scale, the marker only follows the collider.
+        transform: Transform::from_scale(Vec3::splat(collider.radius) / transform.scale),
+        ..default()
+      },
+      RenderLayers::layer(MINIMAP_LAYER),
+      Pickable::IGNORE,
+    )).id();
+    commands.entity(entity).add_child(marker);
+  }
+}
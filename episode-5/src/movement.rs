@@ -1,6 +1,8 @@
 use bevy::prelude::*;
 
-use crate::{collision_detection::Collider, schedule::InGameSet};
+use std::ops::Range;
+
+use crate::{camera::VisibleRange, collision_detection::Collider, schedule::InGameSet};
 
 #[derive(Component, Debug)]
 pub struct Velocity {
@@ -24,6 +26,24 @@ impl Acceleration {
     }
 }
 
+/// Slows an entity down in proportion to its velocity (`v *= 1 - damping * dt`),
+/// so it coasts to a stop once nothing accelerates it and a constant
+/// acceleration `a` settles at a terminal speed of `a / damping`. Entities
+/// without it integrate undamped.
+#[derive(Component, Debug)]
+pub struct Damping(pub f32);
+
+/// Upper bound on an entity's speed, enforced after acceleration and damping.
+/// Entities without it are unbounded.
+#[derive(Component, Debug)]
+pub struct MaxSpeed(pub f32);
+
+/// Entities leaving the visible range re-enter it from the opposite edge
+/// instead of drifting off-screen. Entities without it are eventually
+/// despawned once they are far enough away.
+#[derive(Component, Debug, Default)]
+pub struct WrapAround;
+
 #[derive(Bundle)]
 pub struct MovingObjectBundle {
     pub velocity: Velocity,
@@ -40,8 +60,8 @@ impl Plugin for MovementPlugin
   fn build(&self, app: &mut App)
   {
     app.add_systems(
-      Update,
-      (update_velocity, update_position)
+      FixedUpdate,
+      (update_velocity, update_position, wrap_around)
         .chain()
         .in_set(InGameSet::EntityUpdates),
     );
@@ -49,11 +69,21 @@ impl Plugin for MovementPlugin
 }
 
 
-fn update_velocity(mut query: Query<(&Acceleration, &mut Velocity)>, time: Res<Time>)
+fn update_velocity(mut query: Query<(&Acceleration, &mut Velocity, Option<&Damping>, Option<&MaxSpeed>)>, time: Res<Time>)
 {
-  for (acceleration, mut velocity) in query.iter_mut()
+  for (acceleration, mut velocity, damping, max_speed) in query.iter_mut()
   {
     velocity.value += acceleration.value * time.delta_seconds();
+
+    if let Some(Damping(damping)) = damping
+    {
+      velocity.value *= (1.0 - damping * time.delta_seconds()).max(0.0);
+    }
+
+    if let Some(MaxSpeed(max_speed)) = max_speed
+    {
+      velocity.value = velocity.value.clamp_length_max(*max_speed);
+    }
   }
 }
 
@@ -65,3 +95,42 @@ fn update_position(mut query: Query<(&Velocity, &mut Transform)>, time: Res<Time
     transform.translation += velocity.value * time.delta_seconds();
   }
 }
+
+
+fn wrap_coordinate(value: f32, range: &Range<f32>) -> f32
+{
+  let extent = range.end - range.start;
+  if extent <= 0.0
+  {
+    value
+  }
+  else if value < range.start
+  {
+    value + extent
+  }
+  else if value > range.end
+  {
+    value - extent
+  }
+  else
+  {
+    value
+  }
+}
+
+
+fn wrap_around(mut query: Query<&mut Transform, With<WrapAround>>, visible_range: Res<VisibleRange>)
+{
+  for mut transform in query.iter_mut()
+  {
+    let x = wrap_coordinate(transform.translation.x, &visible_range.x_range);
+    let z = wrap_coordinate(transform.translation.z, &visible_range.z_range);
+
+    // Only touch the transform when wrapping, to keep change detection quiet.
+    if x != transform.translation.x || z != transform.translation.z
+    {
+      transform.translation.x = x;
+      transform.translation.z = z;
+    }
+  }
+}
@@ -0,0 +1,457 @@
+use std::{collections::VecDeque, io, ops::Range, path::Path, sync::OnceLock};
+
+use rand::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::ai_agent::{save_brain, AgentBrain, SavedBrain, NUM_ACTIONS};
+
+
+/// Number of outputs every network produces, one per action.
+pub const NUM_OUTPUTS: usize = NUM_ACTIONS;
+
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum NodeKind
+{
+  Input,
+  Hidden,
+  Output,
+}
+
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct NodeGene
+{
+  pub kind: NodeKind,
+  pub bias: f32,
+}
+
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ConnectionGene
+{
+  pub from: usize,
+  pub to: usize,
+  pub weight: f32,
+  pub enabled: bool,
+}
+
+
+/// Feed forward network evolved with NEAT. Nodes are addressed by their index
+/// in `nodes`: the inputs come first, followed by the outputs and then any
+/// hidden nodes. Hidden and output nodes use a `tanh` activation, so every
+/// output is in [-1, 1].
+///
+/// The network is compiled on its first evaluation and reused until the
+/// genome changes through one of its methods. Call `invalidate` after editing
+/// the public fields directly.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct NeatBrain
+{
+  pub num_inputs: usize,
+  pub nodes: Vec<NodeGene>,
+  pub connections: Vec<ConnectionGene>,
+  #[serde(skip)]
+  compiled: OnceLock<CompiledNetwork>,
+}
+
+
+/// Genomes are equal when their genes are, whether compiled or not.
+impl PartialEq for NeatBrain
+{
+  fn eq(&self, other: &Self) -> bool
+  {
+    self.num_inputs == other.num_inputs && self.nodes == other.nodes && self.connections == other.connections
+  }
+}
+
+
+/// Non-input nodes in evaluation order, each with its enabled incoming
+/// connections, so evaluating doesn't search the connection genes.
+#[derive(Debug, Clone, Default)]
+struct CompiledNetwork
+{
+  /// Node index and range of its inputs in `inputs`.
+  steps: Vec<(usize, Range<usize>)>,
+  /// Source node and weight of every incoming connection.
+  inputs: Vec<(usize, f32)>,
+}
+
+
+/// Probabilities and strengths used by `NeatBrain::mutate`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct MutationRates
+{
+  /// Chance for each connection to have its weight perturbed.
+  pub perturb_weight: f64,
+  /// Largest change applied to a perturbed weight.
+  pub weight_strength: f32,
+  /// Chance to connect two previously unconnected nodes.
+  pub add_connection: f64,
+  /// Chance to split a connection with a new hidden node.
+  pub add_node: f64,
+}
+
+
+impl Default for MutationRates
+{
+  fn default() -> Self
+  {
+    Self
+    {
+      perturb_weight: 0.1,
+      weight_strength: 0.5,
+      add_connection: 0.05,
+      add_node: 0.03,
+    }
+  }
+}
+
+
+impl NeatBrain
+{
+  /// Minimal topology: every input connected directly to every output with a
+  /// random weight and no hidden nodes.
+  pub fn minimal<R: Rng>(num_inputs: usize, rng: &mut R) -> Self
+  {
+    let mut nodes = vec![NodeGene { kind: NodeKind::Input, bias: 0.0 }; num_inputs];
+    nodes.extend((0..NUM_OUTPUTS).map(|_| NodeGene { kind: NodeKind::Output, bias: 0.0 }));
+
+    let connections = (0..num_inputs)
+      .flat_map(|from| (0..NUM_OUTPUTS).map(move |output| (from, num_inputs + output)))
+      .map(|(from, to)| ConnectionGene
+      {
+        from,
+        to,
+        weight: rng.gen_range(-1.0..=1.0),
+        enabled: true,
+      })
+      .collect();
+
+    Self { num_inputs, nodes, connections, compiled: OnceLock::new() }
+  }
+
+  /// Drops the compiled network, so the next evaluation sees the current
+  /// genes.
+  pub fn invalidate(&mut self)
+  {
+    self.compiled = OnceLock::new();
+  }
+
+  pub fn output_nodes(&self) -> Range<usize>
+  {
+    self.num_inputs..self.num_inputs + NUM_OUTPUTS
+  }
+
+  /// Whether the nodes at `output_nodes` exist and are outputs.
+  pub fn has_outputs(&self) -> bool
+  {
+    self.nodes.get(self.output_nodes())
+      .map_or(false, |outputs| outputs.iter().all(|node| node.kind == NodeKind::Output))
+  }
+
+  /// Grows a genome evolved with `num_outputs` outputs to `NUM_OUTPUTS`. The
+  /// new outputs are unconnected, so they stay at zero until mutations
+  /// connect them. Hidden nodes move behind them.
+  pub fn add_outputs(&mut self, num_outputs: usize)
+  {
+    let first_new = self.num_inputs + num_outputs;
+    let missing = NUM_OUTPUTS.saturating_sub(num_outputs);
+    if first_new > self.nodes.len()
+    {
+      // Not a genome with `num_outputs` outputs, left for `has_outputs` to
+      // reject.
+      return;
+    }
+
+    for connection in self.connections.iter_mut()
+    {
+      for node in [&mut connection.from, &mut connection.to]
+      {
+        if *node >= first_new
+        {
+          *node += missing;
+        }
+      }
+    }
+
+    for _ in 0..missing
+    {
+      self.nodes.insert(first_new, NodeGene { kind: NodeKind::Output, bias: 0.0 });
+    }
+    self.invalidate();
+  }
+
+  /// Nodes sorted so every node comes after all of its enabled inputs. Nodes
+  /// that are part of a cycle are left out and always evaluate to zero.
+  fn evaluation_order(&self) -> Vec<usize>
+  {
+    let mut in_degree = vec![0; self.nodes.len()];
+    for connection in self.connections.iter().filter(|c| c.enabled)
+    {
+      in_degree[connection.to] += 1;
+    }
+
+    let mut ready: VecDeque<usize> = (0..self.nodes.len()).filter(|&node| in_degree[node] == 0).collect();
+    let mut order = Vec::with_capacity(self.nodes.len());
+
+    while let Some(node) = ready.pop_front()
+    {
+      order.push(node);
+      for connection in self.connections.iter().filter(|c| c.enabled && c.from == node)
+      {
+        in_degree[connection.to] -= 1;
+        if in_degree[connection.to] == 0
+        {
+          ready.push_back(connection.to);
+        }
+      }
+    }
+
+    order
+  }
+
+  fn compile(&self) -> CompiledNetwork
+  {
+    let mut incoming = vec![vec![]; self.nodes.len()];
+    for connection in self.connections.iter().filter(|c| c.enabled)
+    {
+      incoming[connection.to].push((connection.from, connection.weight));
+    }
+
+    let mut network = CompiledNetwork::default();
+    for node in self.evaluation_order().into_iter().filter(|&node| self.nodes[node].kind != NodeKind::Input)
+    {
+      let start = network.inputs.len();
+      network.inputs.append(&mut incoming[node]);
+      network.steps.push((node, start..network.inputs.len()));
+    }
+    network
+  }
+
+  /// Runs the network on `inputs`. Missing inputs are treated as zero and
+  /// extra ones are ignored, so the output always has `NUM_OUTPUTS` values.
+  pub fn evaluate(&self, inputs: &[f32]) -> Vec<f32>
+  {
+    let network = self.compiled.get_or_init(|| self.compile());
+
+    let mut values = vec![0.0; self.nodes.len()];
+    for (value, &input) in values.iter_mut().zip(inputs).take(self.num_inputs)
+    {
+      *value = input;
+    }
+
+    for (node, inputs) in &network.steps
+    {
+      let weighted_sum: f32 = network.inputs[inputs.clone()]
+        .iter()
+        .map(|&(from, weight)| weight * values[from])
+        .sum();
+
+      values[*node] = (self.nodes[*node].bias + weighted_sum).tanh();
+    }
+
+    values[self.output_nodes()].to_vec()
+  }
+
+  /// Runs the network on every input vector.
+  pub fn evaluate_batch(&self, batch: &[Vec<f32>]) -> Vec<Vec<f32>>
+  {
+    batch.iter().map(|inputs| self.evaluate(inputs)).collect()
+  }
+
+  /// Perturbs weights and, with a small probability, grows the network by a
+  /// connection or a hidden node. Only `rng` decides, so a seeded RNG always
+  /// produces the same mutation.
+  pub fn mutate<R: Rng>(&mut self, rng: &mut R, rates: &MutationRates)
+  {
+    self.invalidate();
+
+    for connection in self.connections.iter_mut()
+    {
+      if rng.gen_bool(rates.perturb_weight)
+      {
+        connection.weight += rng.gen_range(-rates.weight_strength..=rates.weight_strength);
+      }
+    }
+
+    if rng.gen_bool(rates.add_connection)
+    {
+      self.add_connection(rng);
+    }
+
+    if rng.gen_bool(rates.add_node)
+    {
+      self.add_node(rng);
+    }
+  }
+
+  /// Combines two genomes, treating `self` as the fitter parent: the child
+  /// keeps the topology of `self`, and genes present in both parents get the
+  /// average of their weights and biases.
+  pub fn crossover(&self, other: &NeatBrain) -> NeatBrain
+  {
+    let mut child = self.clone();
+    child.invalidate();
+
+    for (index, node) in child.nodes.iter_mut().enumerate()
+    {
+      if let Some(other_node) = other.nodes.get(index).filter(|n| n.kind == node.kind)
+      {
+        node.bias = (node.bias + other_node.bias) / 2.0;
+      }
+    }
+
+    for connection in child.connections.iter_mut()
+    {
+      let matching = other.connections
+        .iter()
+        .find(|c| c.from == connection.from && c.to == connection.to);
+
+      if let Some(other_connection) = matching
+      {
+        connection.weight = (connection.weight + other_connection.weight) / 2.0;
+      }
+    }
+
+    child
+  }
+
+  /// Whether `to` can be reached from `from` following enabled connections.
+  fn reaches(&self, from: usize, to: usize) -> bool
+  {
+    let mut visited = vec![false; self.nodes.len()];
+    let mut pending = vec![from];
+
+    while let Some(node) = pending.pop()
+    {
+      if node == to
+      {
+        return true;
+      }
+
+      if !std::mem::replace(&mut visited[node], true)
+      {
+        pending.extend(self.connections.iter().filter(|c| c.enabled && c.from == node).map(|c| c.to));
+      }
+    }
+
+    false
+  }
+
+  /// Connects a random pair of unconnected nodes, unless that would create a
+  /// cycle.
+  fn add_connection<R: Rng>(&mut self, rng: &mut R)
+  {
+    let sources: Vec<usize> = (0..self.nodes.len()).filter(|&n| self.nodes[n].kind != NodeKind::Output).collect();
+    let targets: Vec<usize> = (0..self.nodes.len()).filter(|&n| self.nodes[n].kind != NodeKind::Input).collect();
+
+    let (Some(&from), Some(&to)) = (sources.choose(rng), targets.choose(rng)) else { return; };
+    let exists = self.connections.iter().any(|c| c.from == from && c.to == to);
+
+    if from != to && !exists && !self.reaches(to, from)
+    {
+      self.connections.push(ConnectionGene
+      {
+        from,
+        to,
+        weight: rng.gen_range(-1.0..=1.0),
+        enabled: true,
+      });
+    }
+  }
+
+  /// Splits a random enabled connection in two with a new hidden node. The
+  /// incoming half has weight 1 and the outgoing half keeps the old weight.
+  fn add_node<R: Rng>(&mut self, rng: &mut R)
+  {
+    let enabled: Vec<usize> = (0..self.connections.len()).filter(|&c| self.connections[c].enabled).collect();
+    let Some(&split) = enabled.choose(rng) else { return; };
+
+    self.connections[split].enabled = false;
+    let ConnectionGene { from, to, weight, .. } = self.connections[split].clone();
+
+    let hidden = self.nodes.len();
+    self.nodes.push(NodeGene { kind: NodeKind::Hidden, bias: 0.0 });
+    self.connections.push(ConnectionGene { from, to: hidden, weight: 1.0, enabled: true });
+    self.connections.push(ConnectionGene { from: hidden, to, weight, enabled: true });
+  }
+}
+
+
+impl AgentBrain for NeatBrain
+{
+  fn process_input(&mut self, sensations: &Vec<f32>) -> Vec<f32>
+  {
+    self.evaluate(sensations)
+  }
+
+  fn process_batch(&mut self, batch: &[Vec<f32>]) -> Vec<Vec<f32>>
+  {
+    self.evaluate_batch(batch)
+  }
+
+  fn save(&self, path: &Path) -> io::Result<()>
+  {
+    save_brain(SavedBrain::Neat(self.clone()), path)
+  }
+}
+
+
+#[cfg(test)]
+mod tests
+{
+  use super::*;
+
+  /// Genome with hidden nodes and disabled connections, not just the minimal
+  /// topology.
+  fn grown_brain() -> NeatBrain
+  {
+    let mut rng = StdRng::seed_from_u64(7);
+    let mut brain = NeatBrain::minimal(5, &mut rng);
+    let rates = MutationRates { perturb_weight: 0.5, weight_strength: 0.5, add_connection: 0.8, add_node: 0.8 };
+    for _ in 0..20
+    {
+      brain.mutate(&mut rng, &rates);
+    }
+    brain
+  }
+
+  #[test]
+  fn crossover_of_identical_parents_is_equivalent()
+  {
+    let parent = grown_brain();
+    assert!(parent.nodes.iter().any(|node| node.kind == NodeKind::Hidden));
+
+    let child = parent.crossover(&parent.clone());
+
+    assert_eq!(child.num_inputs, parent.num_inputs);
+    assert_eq!(child.nodes, parent.nodes);
+    assert_eq!(child.connections, parent.connections);
+
+    let inputs = [0.3, -0.7, 1.0, 0.0, 0.25];
+    assert_eq!(child.evaluate(&inputs), parent.evaluate(&inputs));
+  }
+
+  #[test]
+  fn mutations_recompile_the_network()
+  {
+    let mut rng = StdRng::seed_from_u64(11);
+    let mut brain = grown_brain();
+    let inputs = [0.3, -0.7, 1.0, 0.0, 0.25];
+    brain.evaluate(&inputs);
+
+    let rates = MutationRates { perturb_weight: 1.0, weight_strength: 0.5, add_connection: 1.0, add_node: 1.0 };
+    brain.mutate(&mut rng, &rates);
+
+    let uncompiled = NeatBrain
+    {
+      num_inputs: brain.num_inputs,
+      nodes: brain.nodes.clone(),
+      connections: brain.connections.clone(),
+      compiled: OnceLock::new(),
+    };
+    assert_eq!(brain, uncompiled);
+    assert_eq!(brain.evaluate(&inputs), uncompiled.evaluate(&inputs));
+  }
+}
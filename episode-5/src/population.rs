@@ -0,0 +1,208 @@
+use bevy::prelude::*;
+
+use rand::prelude::*;
+
+use crate::{
+  ai_agent::{Agent, Brain, RandomBrain},
+  asset_loader::SceneAssets,
+  camera::VisibleRange,
+  despawn::{despawn_dead_entities, despawn_far_away_entities, is_dead, is_far_away},
+  fitness::Fitness,
+  health::Health,
+  neat::MutationRates,
+  random::SimulationRng,
+  schedule::InGameSet,
+  spaceship::spawn_spaceship_fleet,
+  state::GameState,
+};
+
+
+const DEFAULT_POPULATION_SIZE: usize = 30;
+/// Longest a generation may live before it is replaced.
+const GENERATION_SECONDS: f32 = 60.0;
+/// Share of a generation, by fitness, that gets to reproduce.
+const PARENT_FRACTION: f32 = 0.2;
+
+
+/// Size of every generation and how many generations have been bred so far.
+/// `size` is seeded from `SpaceshipConfig::count` when the first ships spawn
+/// and kept for all later generations; change it afterwards to resize the
+/// following generations.
+#[derive(Resource, Debug, Clone)]
+pub struct Population
+{
+  pub size: usize,
+  pub generation: u32,
+}
+
+
+impl Default for Population
+{
+  fn default() -> Self
+  {
+    Self
+    {
+      size: DEFAULT_POPULATION_SIZE,
+      generation: 0,
+    }
+  }
+}
+
+
+#[derive(Resource, Debug)]
+struct GenerationTimer(Timer);
+
+
+/// Fitness and brain of every agent of the current generation that has already
+/// been despawned.
+#[derive(Resource, Debug, Default)]
+pub struct FallenAgents(Vec<(f32, Brain)>);
+
+
+pub struct PopulationPlugin;
+
+
+impl Plugin for PopulationPlugin
+{
+  fn build(&self, app: &mut App)
+  {
+    app.init_resource::<Population>()
+      .init_resource::<FallenAgents>()
+      .insert_resource(GenerationTimer(Timer::from_seconds(GENERATION_SECONDS, TimerMode::Once)))
+      .add_systems(
+        Update,
+        (
+          record_fallen_agents
+            .before(despawn_dead_entities)
+            .before(despawn_far_away_entities),
+          next_generation
+            .after(despawn_dead_entities)
+            .after(despawn_far_away_entities),
+        )
+        .in_set(InGameSet::DespawnEntities),
+      )
+      .add_systems(OnEnter(GameState::GameOver), reset_generation_timer);
+  }
+}
+
+
+fn reset_generation_timer(mut timer: ResMut<GenerationTimer>)
+{
+  timer.0.reset();
+}
+
+
+fn agent_brain<'a>(children: &Children, brains: &'a Query<&Brain>) -> Option<&'a Brain>
+{
+  children.iter().find_map(|&child| brains.get(child).ok())
+}
+
+
+/// Remembers agents that are about to be despawned, so they can still be
+/// selected as parents once their generation is over.
+fn record_fallen_agents(agents: Query<(&Fitness, &Health, &GlobalTransform, &Children), With<Agent>>,
+                        brains: Query<&Brain>,
+                        visible_range: Res<VisibleRange>,
+                        mut fallen: ResMut<FallenAgents>,
+)
+{
+  for (fitness, health, transform, children) in agents.iter()
+  {
+    // Same checks the despawn systems this runs before use.
+    if is_dead(health) || is_far_away(transform, &visible_range)
+    {
+      if let Some(brain) = agent_brain(children, &brains)
+      {
+        fallen.0.push((fitness.0, brain.clone()));
+      }
+    }
+  }
+}
+
+
+/// Replaces the population once the generation timer runs out. A population
+/// that dies out before that ends the game, and the next generation is spawned
+/// when the game restarts.
+#[allow(clippy::too_many_arguments)]
+fn next_generation(mut commands: Commands,
+                   agents: Query<(Entity, &Fitness, &Children), With<Agent>>,
+                   brains: Query<&Brain>,
+                   mut fallen: ResMut<FallenAgents>,
+                   mut timer: ResMut<GenerationTimer>,
+                   mut population: ResMut<Population>,
+                   scene_assets: Res<SceneAssets>,
+                   visible_range: Res<VisibleRange>,
+                   mut rng: ResMut<SimulationRng>,
+                   time: Res<Time>,
+)
+{
+  timer.0.tick(time.delta());
+  if !timer.0.finished()
+  {
+    return;
+  }
+
+  // Survivors of an expired generation compete with the fallen ones.
+  for (entity, fitness, children) in agents.iter()
+  {
+    if let Some(brain) = agent_brain(children, &brains)
+    {
+      fallen.0.push((fitness.0, brain.clone()));
+    }
+    commands.entity(entity).despawn_recursive();
+  }
+
+  let offspring = breed_next_generation(&mut population, &mut fallen, rng.as_mut());
+  timer.0.reset();
+
+  spawn_spaceship_fleet(&mut commands, &scene_assets, &visible_range, offspring, rng.as_mut());
+}
+
+
+/// Brains of the next generation, bred from the fittest fallen agents, which
+/// are consumed. Without any fallen agents the brains are random and the
+/// generation counter stays put.
+pub fn breed_next_generation<R: Rng>(population: &mut Population, fallen: &mut FallenAgents, rng: &mut R) -> Vec<Brain>
+{
+  let mut ranked = std::mem::take(&mut fallen.0);
+  ranked.sort_by(|a, b| b.0.total_cmp(&a.0));
+  let num_parents = (ranked.len() as f32 * PARENT_FRACTION).ceil() as usize;
+  let parents: Vec<Brain> = ranked.into_iter().take(num_parents).map(|(_, brain)| brain).collect();
+
+  if !parents.is_empty()
+  {
+    population.generation += 1;
+    info!("Starting generation {} with {} parents", population.generation, parents.len());
+  }
+
+  (0..population.size).map(|_| breed(&parents, rng)).collect()
+}
+
+
+/// Child of two random parents, given fittest first. Network brains are
+/// crossed over and mutated, every other brain is replaced by a random one.
+fn breed<R: Rng>(parents: &[Brain], rng: &mut R) -> Brain
+{
+  if parents.is_empty()
+  {
+    return Brain::Random(RandomBrain::seeded(rng.gen()));
+  }
+
+  let first = rng.gen_range(0..parents.len());
+  let second = rng.gen_range(0..parents.len());
+
+  match (&parents[first.min(second)], &parents[first.max(second)])
+  {
+    (Brain::Neat(first), second) =>
+    {
+      let mut child = match second
+      {
+        Brain::Neat(second) => first.crossover(second),
+        _ => first.clone(),
+      };
+      child.mutate(rng, &MutationRates::default());
+      Brain::Neat(child)
+    },
+    _ => Brain::Random(RandomBrain::seeded(rng.gen())),
+  }
+}
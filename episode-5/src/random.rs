@@ -0,0 +1,104 @@
+use bevy::prelude::*;
+use rand::{rngs::StdRng, RngCore, SeedableRng};
+
+
+/// Seed of the `SimulationRng`, making episodes reproducible. Without it the
+/// simulation draws its seed from entropy. Changing it reseeds the RNG.
+#[derive(Resource, Debug, Clone, Copy)]
+pub struct SimulationSeed(pub u64);
+
+
+/// The one source of randomness of the simulation, so that a `SimulationSeed`
+/// reproduces a whole run. Its consumers, in the order they draw from it:
+///
+/// 1. `spawn_spaceships` (`PostStartup` and entering `GameOver`) breeds the
+///    fleet, seeding random brains and mutating network ones, then places the
+///    ships.
+/// 2. `next_generation` (`InGameSet::DespawnEntities`) does the same for every
+///    later generation.
+/// 3. `spawn_asteroid` (`InGameSet::EntityUpdates`) picks the position,
+///    acceleration and size of new asteroids.
+///
+/// Random brains then only use their own seeded RNG. New consumers have to
+/// be ordered against the existing ones, or the sequence stops being stable.
+#[derive(Resource, Debug)]
+pub struct SimulationRng
+{
+  seed: Option<u64>,
+  rng: StdRng,
+}
+
+
+impl SimulationRng
+{
+  pub fn seeded(seed: u64) -> Self
+  {
+    Self { seed: Some(seed), rng: StdRng::seed_from_u64(seed) }
+  }
+
+  /// Seed the RNG started from, `None` if it came from entropy.
+  pub fn seed(&self) -> Option<u64>
+  {
+    self.seed
+  }
+}
+
+
+impl FromWorld for SimulationRng
+{
+  fn from_world(world: &mut World) -> Self
+  {
+    match world.get_resource::<SimulationSeed>()
+    {
+      Some(seed) => Self::seeded(seed.0),
+      None => Self { seed: None, rng: StdRng::from_entropy() },
+    }
+  }
+}
+
+
+impl RngCore for SimulationRng
+{
+  fn next_u32(&mut self) -> u32
+  {
+    self.rng.next_u32()
+  }
+
+  fn next_u64(&mut self) -> u64
+  {
+    self.rng.next_u64()
+  }
+
+  fn fill_bytes(&mut self, dest: &mut [u8])
+  {
+    self.rng.fill_bytes(dest)
+  }
+
+  fn try_fill_bytes(&mut self, dest: &mut [u8]) -> Result<(), rand::Error>
+  {
+    self.rng.try_fill_bytes(dest)
+  }
+}
+
+
+pub struct RandomPlugin;
+
+
+impl Plugin for RandomPlugin
+{
+  fn build(&self, app: &mut App)
+  {
+    app.init_resource::<SimulationRng>()
+      .add_systems(First, reseed_rng.run_if(resource_exists_and_changed::<SimulationSeed>));
+  }
+}
+
+
+/// Restarts the RNG when a different seed is inserted after startup.
+fn reseed_rng(seed: Res<SimulationSeed>, mut rng: ResMut<SimulationRng>)
+{
+  if rng.seed() != Some(seed.0)
+  {
+    *rng = SimulationRng::seeded(seed.0);
+  }
+}
@@ -1,4 +1,4 @@
-use bevy::prelude::*;
+use bevy::{ecs::schedule::ScheduleLabel, prelude::*};
 
 use crate::state::GameState;
 
@@ -10,27 +10,66 @@ pub enum InGameSet {
     CollisionDetection,
 }
 
+const DEFAULT_PHYSICS_HZ: f64 = 60.0;
+
+/// Rate at which the physics systems (movement, collisions, fitness) step in
+/// `FixedUpdate`. Every step advances the simulation by the same `1 / hz`
+/// seconds regardless of the frame rate, which keeps runs reproducible.
+#[derive(Resource, Debug, Clone, Copy)]
+pub struct PhysicsTimestep {
+    pub hz: f64,
+}
+
+impl Default for PhysicsTimestep {
+    fn default() -> Self {
+        Self { hz: DEFAULT_PHYSICS_HZ }
+    }
+}
+
+/// Runs the `InGameSet`s in both `Update` and `FixedUpdate`, the latter at the
+/// `PhysicsTimestep` rate. Rendering is not interpolated between fixed steps:
+/// frames show the state of the last completed step, so motion can judder
+/// when the frame rate isn't a multiple of the physics rate. The simulation
+/// itself doesn't depend on the frame rate.
 pub struct SchedulePlugin;
 
 impl Plugin for SchedulePlugin {
     fn build(&self, app: &mut App) {
-        app.configure_sets(
-            Update,
-            (
-                InGameSet::DespawnEntities,
-                // Flush commands (i.e. `apply_deferred` runs)
-                InGameSet::UserInput,
-                InGameSet::EntityUpdates,
-                InGameSet::CollisionDetection,
+        app.init_resource::<PhysicsTimestep>();
+        let physics_hz = app.world.resource::<PhysicsTimestep>().hz;
+
+        configure_in_game_sets(app, Update);
+        configure_in_game_sets(app, FixedUpdate);
+
+        app.insert_resource(Time::<Fixed>::from_hz(physics_hz))
+            .add_systems(
+                Update,
+                apply_physics_timestep.run_if(resource_changed::<PhysicsTimestep>),
             )
-                .chain()
-                .run_if(in_state(GameState::InGame)),
-        )
-        .add_systems(
-            Update,
-            apply_deferred
-                .after(InGameSet::DespawnEntities)
-                .before(InGameSet::UserInput),
-        );
+            .add_systems(
+                Update,
+                apply_deferred
+                    .after(InGameSet::DespawnEntities)
+                    .before(InGameSet::UserInput),
+            );
     }
 }
+
+fn configure_in_game_sets(app: &mut App, schedule: impl ScheduleLabel) {
+    app.configure_sets(
+        schedule,
+        (
+            InGameSet::DespawnEntities,
+            // Flush commands (i.e. `apply_deferred` runs)
+            InGameSet::UserInput,
+            InGameSet::EntityUpdates,
+            InGameSet::CollisionDetection,
+        )
+            .chain()
+            .run_if(in_state(GameState::InGame)),
+    );
+}
+
+fn apply_physics_timestep(timestep: Res<PhysicsTimestep>, mut fixed_time: ResMut<Time<Fixed>>) {
+    fixed_time.set_timestep_hz(timestep.hz);
+}
@@ -0,0 +1,73 @@
+use bevy::prelude::*;
+
+use crate::{
+  asteroids::Asteroid,
+  collision_detection::apply_collision_damage,
+  event_handler::MissileOwner,
+  health::DeathEvent,
+  schedule::InGameSet,
+  state::GameState,
+};
+
+
+/// Points for destroying a medium asteroid, scaled by `Asteroid::size` so
+/// larger asteroids are worth more.
+const ASTEROID_POINTS: f32 = 100.0;
+
+
+/// Points scored in the current game by shooting down asteroids.
+#[derive(Resource, Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Score(pub u32);
+
+
+pub struct ScorePlugin;
+
+
+impl Plugin for ScorePlugin
+{
+  fn build(&self, app: &mut App)
+  {
+    app.init_resource::<Score>()
+      .add_systems(
+        FixedUpdate,
+        score_destroyed_asteroids
+          .after(apply_collision_damage)
+          .in_set(InGameSet::EntityUpdates),
+      )
+      .add_systems(OnEnter(GameState::GameOver), reset_score);
+  }
+}
+
+
+pub fn asteroid_points(asteroid: &Asteroid) -> u32
+{
+  (ASTEROID_POINTS * asteroid.size).round() as u32
+}
+
+
+fn score_destroyed_asteroids(mut death_event_reader: EventReader<DeathEvent>,
+                             asteroids: Query<&Asteroid>,
+                             missiles: Query<(), With<MissileOwner>>,
+                             mut score: ResMut<Score>,
+)
+{
+  for &DeathEvent { entity, killed_by } in death_event_reader.read()
+  {
+    let Some(killer) = killed_by else { continue; };
+    if !missiles.contains(killer)
+    {
+      continue;
+    }
+
+    if let Ok(asteroid) = asteroids.get(entity)
+    {
+      score.0 += asteroid_points(asteroid);
+    }
+  }
+}
+
+
+fn reset_score(mut score: ResMut<Score>)
+{
+  score.0 = 0;
+}
@@ -0,0 +1,151 @@
+use std::time::Duration;
+
+use bevy::{app::PluginsState, prelude::*, time::TimeUpdateStrategy};
+
+use crate::{
+  ai_agent::{Agent, AgentObservations, Brain, ExternalBrain, NUM_ACTIONS},
+  build_app,
+  schedule::PhysicsTimestep,
+};
+
+
+/// Runs the simulation from an outside loop instead of Bevy's runner, e.g.
+/// for reinforcement learning: set the actions of the agents, advance exactly
+/// one tick with `step` and read back what every agent sensed. Time doesn't
+/// follow the wall clock, every step advances it by one `PhysicsTimestep`.
+pub struct HeadlessSim
+{
+  app: App,
+}
+
+
+impl HeadlessSim
+{
+  /// Simulation without a window, the vision cameras still render.
+  pub fn new() -> Self
+  {
+    Self::from_app(build_app(true))
+  }
+
+  /// Takes over `app` and runs its first update, which runs the startup
+  /// systems but no physics tick yet.
+  pub fn from_app(mut app: App) -> Self
+  {
+    app.init_resource::<AgentObservations>();
+
+    // Mirrors what `App::run` does before the first update.
+    while app.plugins_state() == PluginsState::Adding
+    {
+      bevy::tasks::tick_global_task_pools_on_main_thread();
+    }
+    app.finish();
+    app.cleanup();
+
+    let mut sim = Self { app };
+    sim.advance_time_by_timestep();
+    // The first update only starts the clock, time advances from the next one.
+    sim.app.update();
+    sim
+  }
+
+  /// Makes the next update advance time by exactly one physics timestep, so
+  /// `FixedUpdate` runs once.
+  fn advance_time_by_timestep(&mut self)
+  {
+    let hz = self.app.world.get_resource::<PhysicsTimestep>().copied().unwrap_or_default().hz;
+    self.app.insert_resource(TimeUpdateStrategy::ManualDuration(Duration::from_secs_f64(1.0 / hz)));
+  }
+
+  pub fn app_mut(&mut self) -> &mut App
+  {
+    &mut self.app
+  }
+
+  /// All live agents, in no particular order.
+  pub fn agents(&mut self) -> Vec<Entity>
+  {
+    let mut agents = self.app.world.query_filtered::<Entity, With<Agent>>();
+    agents.iter(&self.app.world).collect()
+  }
+
+  /// Makes `agent` perform `action` every step until it is changed, replacing
+  /// its brain with an external one if needed. Returns false if the entity
+  /// has no brain.
+  pub fn set_action(&mut self, agent: Entity, action: [f32; NUM_ACTIONS]) -> bool
+  {
+    let Some(children) = self.app.world.get::<Children>(agent) else { return false; };
+    let children: Vec<Entity> = children.iter().copied().collect();
+
+    for child in children
+    {
+      if let Some(mut brain) = self.app.world.get_mut::<Brain>(child)
+      {
+        match brain.as_mut()
+        {
+          Brain::External(brain) => brain.action = action,
+          brain => *brain = Brain::External(ExternalBrain { action }),
+        }
+        return true;
+      }
+    }
+    false
+  }
+
+  /// Advances the simulation by one update, running exactly one physics tick.
+  pub fn step(&mut self)
+  {
+    self.advance_time_by_timestep();
+    self.app.update();
+  }
+
+  /// Sensations `agent` acted upon during the last step, in the layout its
+  /// brain receives them. `None` if the agent didn't act.
+  pub fn observation(&self, agent: Entity) -> Option<Vec<f32>>
+  {
+    self.app.world.get_resource::<AgentObservations>()?.0.get(&agent).cloned()
+  }
+}
+
+
+impl Default for HeadlessSim
+{
+  fn default() -> Self
+  {
+    Self::new()
+  }
+}
+
+
+#[cfg(test)]
+mod tests
+{
+  use super::*;
+  use crate::{
+    camera::VisibleRange,
+    movement::{Acceleration, MovementPlugin, Velocity},
+    schedule::SchedulePlugin,
+    state::GameState,
+  };
+
+  #[test]
+  fn a_step_moves_bodies_by_one_timestep()
+  {
+    let mut app = App::new();
+    app.add_plugins((MinimalPlugins, SchedulePlugin, MovementPlugin))
+      .init_state::<GameState>()
+      .init_resource::<VisibleRange>();
+    let velocity = Vec3::new(3.0, 0.0, -1.5);
+    let body = app.world.spawn((Velocity::new(velocity), Acceleration::new(Vec3::ZERO), Transform::default())).id();
+
+    let mut sim = HeadlessSim::from_app(app);
+    let dt = 1.0 / sim.app_mut().world.resource::<PhysicsTimestep>().hz as f32;
+
+    for _ in 0..3
+    {
+      let before = sim.app_mut().world.get::<Transform>(body).unwrap().translation;
+      sim.step();
+      let after = sim.app_mut().world.get::<Transform>(body).unwrap().translation;
+      assert!((after - before - velocity * dt).length() < 1e-5, "moved {:?}, expected {:?}", after - before, velocity * dt);
+    }
+  }
+}
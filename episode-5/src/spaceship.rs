@@ -1,15 +1,23 @@
 use bevy::{prelude::*, transform};
+use bevy_mod_picking::prelude::Pickable;
 
 use rand::prelude::*;
 
 
 use crate::{
-  ai_agent::{Agent, Brain, RandomBrain},
+  ai_agent::{Agent, Brain, Locomotion},
+  ai_framework::{SelfStateSensor, Sensor},
   asset_loader::SceneAssets,
   camera::{update_visible_range, VisibleRange},
-  collision_detection::{Collider, CollisionDamage},
-  health::Health,
-  movement::{Acceleration, MovingObjectBundle, Velocity},
+  collision_detection::{layers, Collider, CollisionDamage, CollisionLayer},
+  despawn::Lifetime,
+  event_handler::{missile_collision_layer, tick_weapon_cooldowns, MissileOwner, WeaponCooldown},
+  fitness::Fitness,
+  population::{breed_next_generation, FallenAgents, Population},
+  random::SimulationRng,
+  health::{Health, HealthRegen, Invulnerable},
+  input::{InputBindings, PlayerInput},
+  movement::{Acceleration, Damping, MaxSpeed, MovingObjectBundle, Velocity, WrapAround},
   schedule::InGameSet,
   state::GameState,
   vision::VisionObjectBundle
@@ -18,26 +26,71 @@ use crate::{
 
 const SPACESHIP_RADIUS: f32 = 0.65;
 const SPACESHIP_SPEED: f32 = 15.0;
+const SPACESHIP_THRUST: f32 = 20.0;
+const SPACESHIP_DAMPING: f32 = 1.0;
 const SPACESHIP_ROTATION_SPEED: f32 = 2.5;
 const SPACESHIP_ROLL_SPEED: f32 = 2.5;
 const SPACESHIP_HEALTH: f32 = 100.0;
+const SPACESHIP_HEALTH_REGEN_RATE: f32 = 5.0;
+const SPACESHIP_HEALTH_REGEN_DELAY: f32 = 3.0;
+const SPACESHIP_INVULNERABLE_SECONDS: f32 = 0.5;
 const SPACESHIP_COLLISION_DAMAGE: f32 = 100.0;
 const SPACESHIP_SCALE: Vec3 = Vec3::splat(0.2);
+const SHIELD_DURATION_SECONDS: f32 = 3.0;
+const SHIELD_RADIUS: f32 = 1.0;
 const MISSILE_SPEED: f32 = 25.0;
 const MISSILE_FORWARD_SPAWN_SCALAR: f32 = 2.0;
 const MISSILE_RADIUS: f32 = 0.3;
 const MISSILE_HEALTH: f32 = 1.0;
 const MISSILE_COLLISION_DAMAGE: f32 = 5.0;
 const MISSILE_SCALE: Vec3 = Vec3::splat(0.3);
-const NUM_SPACESHIPS: u16 = 30;
+const PROXIMITY_RANGE: f32 = 20.0;
+const PROXIMITY_RAYS: usize = 8;
 
 
 #[derive(Component, Debug)]
 pub struct Spaceship;
 
 
+/// How many spaceships spawn at startup. Set it before `PostStartup` to change
+/// the size of the first generation. It only seeds `Population::size`, which
+/// sizes all later generations and isn't reset from this on respawns.
+#[derive(Resource, Debug, Clone)]
+pub struct SpaceshipConfig
+{
+  pub count: u16,
+}
+
+
+impl Default for SpaceshipConfig
+{
+  fn default() -> Self
+  {
+    Self { count: 30 }
+  }
+}
+
+
+/// Protects a spaceship from all collision damage until its timer runs out.
 #[derive(Component, Debug)]
-pub struct SpaceshipShield;
+pub struct SpaceshipShield
+{
+  pub timer: Timer,
+  visual: Option<Entity>,
+}
+
+
+impl SpaceshipShield
+{
+  pub fn new(seconds: f32) -> Self
+  {
+    Self
+    {
+      timer: Timer::from_seconds(seconds, TimerMode::Once),
+      visual: None,
+    }
+  }
+}
 
 
 #[derive(Component, Debug)]
@@ -51,14 +104,17 @@ impl Plugin for SpaceshipPlugin
 {
   fn build(&self, app: &mut App)
   {
-    app.add_systems(PostStartup, spawn_spaceships.after(update_visible_range))
+    app.init_resource::<SpaceshipConfig>()
+      .init_resource::<InputBindings>()
+      .add_systems(PostStartup, (seed_population_size, spawn_spaceships).chain().after(update_visible_range))
       .add_systems(OnEnter(GameState::GameOver), spawn_spaceships)
       .add_systems(
         Update,
         (
 //          spaceship_movement_controls,
-          spaceship_weapon_controls,
+          spaceship_weapon_controls.after(tick_weapon_cooldowns),
           spaceship_shield_controls,
+          expire_shields,
         )
         .chain()
         .in_set(InGameSet::UserInput),
@@ -68,15 +124,39 @@ impl Plugin for SpaceshipPlugin
 }
 
 
+/// Sizes the population from the `SpaceshipConfig` once, before the first
+/// fleet spawns.
+fn seed_population_size(spaceship_config: Res<SpaceshipConfig>, mut population: ResMut<Population>)
+{
+  population.size = spaceship_config.count as usize;
+}
+
+
 fn spawn_spaceships(mut commands: Commands,
                     scene_assets: Res<SceneAssets>,
                     visible_range: Res<VisibleRange>,
+                    mut population: ResMut<Population>,
+                    mut fallen: ResMut<FallenAgents>,
+                    mut rng: ResMut<SimulationRng>,
+)
+{
+  // The first fleet gets random brains, later ones descend from the fallen.
+  let brains = breed_next_generation(&mut population, &mut fallen, rng.as_mut());
+  spawn_spaceship_fleet(&mut commands, &scene_assets, &visible_range, brains, rng.as_mut());
+}
+
+
+/// Spawns one spaceship per brain at random locations in the visible range.
+pub fn spawn_spaceship_fleet(commands: &mut Commands,
+                             scene_assets: &Res<SceneAssets>,
+                             visible_range: &VisibleRange,
+                             brains: impl IntoIterator<Item = Brain>,
+                             rng: &mut impl Rng,
 )
 {
-  let mut rng = rand::thread_rng();
 
   let id_offset = 2;
-  for spaceship_num in 0..NUM_SPACESHIPS
+  for (spaceship_num, brain) in (0..).zip(brains)
   {
     let location = Vec3::new(
       rng.gen_range(visible_range.x_range.clone()),
@@ -84,7 +164,7 @@ fn spawn_spaceships(mut commands: Commands,
       rng.gen_range(visible_range.z_range.clone()),
     );
 
-    spawn_spaceship(&mut commands, &scene_assets, location, spaceship_num + id_offset);
+    spawn_spaceship(commands, scene_assets, location, spaceship_num + id_offset, brain);
   }
 }
 
@@ -92,7 +172,8 @@ fn spawn_spaceships(mut commands: Commands,
 fn spawn_spaceship(commands: &mut Commands,
                    scene_assets: &Res<SceneAssets>,
                    location: Vec3,
-                   spaceship_num: u16
+                   spaceship_num: u16,
+                   brain: Brain,
 )
 {
   commands.spawn((
@@ -111,47 +192,40 @@ fn spawn_spaceship(commands: &mut Commands,
     Spaceship,
     VisionObjectBundle::new(spaceship_num as isize),
     Agent,
+    Locomotion::default(),
+    WeaponCooldown::default(),
+    Fitness::default(),
+    Damping(SPACESHIP_DAMPING),
+    MaxSpeed(SPACESHIP_SPEED),
+    WrapAround,
+    CollisionLayer::new(layers::SPACESHIPS, layers::ALL),
     Health::new(SPACESHIP_HEALTH),
+    HealthRegen::new(SPACESHIP_HEALTH_REGEN_RATE, SPACESHIP_HEALTH_REGEN_DELAY),
+    Invulnerable::new(SPACESHIP_INVULNERABLE_SECONDS),
     CollisionDamage::new(SPACESHIP_COLLISION_DAMAGE),
   ))
   .with_children(|parent| {
-    parent.spawn(Brain::Random(RandomBrain::default()));
+    parent.spawn(brain);
+    parent.spawn(Sensor::Proximity { range: PROXIMITY_RANGE, num_rays: PROXIMITY_RAYS });
+    parent.spawn(Sensor::SelfState(SelfStateSensor::new(SPACESHIP_SPEED, SPACESHIP_HEALTH)));
   });
 }
 
 
 fn spaceship_movement_controls(
-    mut query: Query<(&mut Transform, &mut Velocity), With<Spaceship>>,
-    keyboard_input: Res<ButtonInput<KeyCode>>,
+    mut query: Query<(&mut Transform, &mut Acceleration), With<Spaceship>>,
+    player_input: PlayerInput,
     time: Res<Time>,
 )
 {
-  let Ok((_transform, _velocity)) = query.get_single_mut() else {
+  let Ok((_transform, _acceleration)) = query.get_single_mut() else {
       return;
   };
-  for (mut transform, mut velocity) in query.iter_mut()
+  for (mut transform, mut acceleration) in query.iter_mut()
   {
-    let mut rotation = 0.0;
-    let mut roll = 0.0;
-    let mut movement = 0.0;
-
-    if keyboard_input.pressed(KeyCode::KeyD) {
-        rotation = -SPACESHIP_ROTATION_SPEED * time.delta_seconds();
-    } else if keyboard_input.pressed(KeyCode::KeyA) {
-        rotation = SPACESHIP_ROTATION_SPEED * time.delta_seconds();
-    }
-
-    if keyboard_input.pressed(KeyCode::KeyS) {
-        movement = -SPACESHIP_SPEED;
-    } else if keyboard_input.pressed(KeyCode::KeyW) {
-        movement = SPACESHIP_SPEED;
-    }
-
-    if keyboard_input.pressed(KeyCode::ShiftLeft) {
-        roll = -SPACESHIP_ROLL_SPEED * time.delta_seconds();
-    } else if keyboard_input.pressed(KeyCode::ControlLeft) {
-        roll = SPACESHIP_ROLL_SPEED * time.delta_seconds();
-    }
+    let rotation = player_input.yaw() * SPACESHIP_ROTATION_SPEED * time.delta_seconds();
+    let roll = player_input.roll() * SPACESHIP_ROLL_SPEED * time.delta_seconds();
+    let thrust = player_input.thrust() * SPACESHIP_THRUST;
 
     // Rotate around the Y-axis.
     // Ignores the Z-axis rotation applied below.
@@ -161,16 +235,16 @@ fn spaceship_movement_controls(
     // The rotation is relative to the current rotation!
     transform.rotate_local_z(roll);
 
-    // Update the spaceship's velocity based on new direction.
-    velocity.value = transform.forward() * movement;
+    // Thrust along the new direction, damping takes care of slowing down.
+    acceleration.value = transform.forward() * thrust;
   }
 }
 
 
 fn spaceship_weapon_controls(
     mut commands: Commands,
-    query: Query<&Transform, With<Spaceship>>,
-    keyboard_input: Res<ButtonInput<KeyCode>>,
+    mut query: Query<(Entity, &Transform, Option<&mut WeaponCooldown>), With<Spaceship>>,
+    player_input: PlayerInput,
     scene_assets: Res<SceneAssets>,
 )
 {
@@ -178,10 +252,18 @@ fn spaceship_weapon_controls(
 //    return;
 //  };
 
-  if keyboard_input.pressed(KeyCode::Space)
+  if player_input.fire()
   {
-    for transform in query.iter()
+    for (spaceship, transform, cooldown) in query.iter_mut()
     {
+      if let Some(mut cooldown) = cooldown
+      {
+        if !cooldown.try_fire()
+        {
+          continue;
+        }
+      }
+
       commands.spawn((
         MovingObjectBundle
         {
@@ -197,6 +279,9 @@ fn spaceship_weapon_controls(
           },
         },
         SpaceshipMissile,
+        MissileOwner(spaceship),
+        missile_collision_layer(),
+        Lifetime::default(),
         Health::new(MISSILE_HEALTH),
         CollisionDamage::new(MISSILE_COLLISION_DAMAGE),
       ));
@@ -207,18 +292,68 @@ fn spaceship_weapon_controls(
 
 fn spaceship_shield_controls(
     mut commands: Commands,
-    query: Query<Entity, With<Spaceship>>,
-    keyboard_input: Res<ButtonInput<KeyCode>>,
+    query: Query<Entity, (With<Spaceship>, Without<SpaceshipShield>)>,
+    player_input: PlayerInput,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
 )
 {
-  let Ok(spaceship) = query.get_single() else
+  if !player_input.shield() || query.is_empty()
   {
     return;
-  };
+  }
+
+  let mesh = meshes.add(Sphere::new(SHIELD_RADIUS / SPACESHIP_SCALE.x));
+  let material = materials.add(StandardMaterial
+  {
+    base_color: Color::rgba(0.3, 0.6, 1.0, 0.3),
+    alpha_mode: AlphaMode::Blend,
+    unlit: true,
+    ..default()
+  });
+
+  for spaceship in query.iter()
+  {
+    // The bubble is not pickable, which also keeps it out of the vision layer.
+    let visual = commands.spawn((
+      PbrBundle
+      {
+        mesh: mesh.clone(),
+        material: material.clone(),
+        ..default()
+      },
+      Pickable::IGNORE,
+    )).id();
+
+    let mut shield = SpaceshipShield::new(SHIELD_DURATION_SECONDS);
+    shield.visual = Some(visual);
 
-  if keyboard_input.pressed(KeyCode::Tab)
+    commands.entity(spaceship)
+      .insert(shield)
+      .add_child(visual);
+  }
+}
+
+
+fn expire_shields(
+    mut commands: Commands,
+    mut query: Query<(Entity, &mut SpaceshipShield)>,
+    time: Res<Time>,
+)
+{
+  for (spaceship, mut shield) in query.iter_mut()
   {
-    commands.entity(spaceship).insert(SpaceshipShield);
+    shield.timer.tick(time.delta());
+    if !shield.timer.finished()
+    {
+      continue;
+    }
+
+    if let Some(visual) = shield.visual
+    {
+      commands.entity(visual).despawn_recursive();
+    }
+    commands.entity(spaceship).remove::<SpaceshipShield>();
   }
 }
 
@@ -228,9 +363,10 @@ fn spaceship_destroyed(
     query: Query<(), With<Spaceship>>,
 )
 {
-//  if query.get_single().is_err()
-//  {
-//    info!("Game Over!");
-//    next_state.set(GameState::GameOver);
-//  };
+  // Only the loss of the whole fleet ends the game, single ships may die.
+  if query.is_empty()
+  {
+    info!("Game Over!");
+    next_state.set(GameState::GameOver);
+  }
 }
@@ -5,6 +5,8 @@ pub enum GameState
 {
   #[default]
   InGame,
+  /// Toggled with Escape. Freezes the simulation (`InGameSet` doesn't run),
+  /// while vision selection and camera controls keep working.
   Paused,
   GameOver,
 }
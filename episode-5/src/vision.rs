@@ -1,24 +1,34 @@
-use std::marker::PhantomData;
+use std::{marker::PhantomData, sync::Arc};
 
 use bevy::{
   prelude::*,
   render::{
-    camera::{Viewport, ClearColorConfig},
+    camera::{Viewport, ClearColorConfig, RenderTarget, ScalingMode},
+    render_resource::TextureFormat,
     view::RenderLayers
   },
-  ecs::system::SystemParam
+  ecs::system::SystemParam,
+  utils::HashMap,
+  window::{PrimaryWindow, WindowRef, WindowResized},
 };
 
-use bevy_mod_picking::prelude::*;
+use bevy_mod_picking::{highlight::InitialHighlight, prelude::*};
 
 use crate::schedule::InGameSet;
 use crate::ai_framework::Sensor;
 
-use gpu_copy::{ImageSource, ExportedImages};
-use image::{GenericImageView, ImageBuffer, Rgba};
+use gpu_copy::{FramePathTemplate, ImageSource, ExportedImages, ImageExportSettings, ImageSaver, ImageWrapper, RenderTargetConfig};
+use image::{DynamicImage, GenericImageView, ImageBuffer, Rgba, RgbaImage};
 
 
-const VISION: &str = "Vision";
+pub const DEFAULT_VISION_TARGET: &str = "Vision";
+const VISION_FRAME_TEMPLATE: &str = "ai_agent_{name}_{frame:06}.{ext}";
+
+/// Layer rendered by the vision cameras. Scene meshes stay on the default
+/// layer and get an untinted `VisionTwin` on this one, while gizmos and the
+/// selection highlight stay on the default layer only, so agents never see
+/// debug overlays.
+pub const VISION_LAYER: u8 = 1;
 
 #[derive(Debug, Default, Clone)]
 pub struct ViewParams
@@ -30,39 +40,308 @@ pub struct ViewParams
 }
 
 
+/// Latest frames of every vision target, oldest first, taken once per frame
+/// by `snapshot_vision` so sensing doesn't lock `ExportedImages` for every
+/// agent. Holds more than one frame when a vision stacks frames.
+#[derive(Resource, Debug, Default)]
+pub struct VisionSnapshot(pub HashMap<String, Vec<Arc<ImageWrapper>>>);
+
+
+/// Render targets of all `VisionPlugin`s, in the order the plugins were
+/// added. Visions render into the one named by `Vision::target`, or into the
+/// first one if they don't name a target that exists.
+#[derive(Resource, Debug, Default, Clone)]
+pub struct VisionTargets(pub Vec<String>);
+
+
+impl VisionTargets
+{
+  pub fn primary(&self) -> &str
+  {
+    self.0.first().map_or(DEFAULT_VISION_TARGET, String::as_str)
+  }
+
+  pub fn contains(&self, target_name: &str) -> bool
+  {
+    self.0.iter().any(|name| name == target_name)
+  }
+
+  /// Target `vision` renders into.
+  pub fn resolve<'a>(&'a self, vision: &'a Vision) -> &'a str
+  {
+    vision.target.as_deref()
+      .filter(|target_name| self.contains(target_name))
+      .unwrap_or_else(|| self.primary())
+  }
+}
+
+
 #[derive(SystemParam)]
 pub struct VisionView<'w, 's>
 {
-  exported_images: Res<'w, ExportedImages>,
+  snapshot: Res<'w, VisionSnapshot>,
+  targets: Res<'w, VisionTargets>,
   marker: PhantomData<&'s ()>,
 }
 
 
+/// Part of the vision frame seen by one vision, borrowed from the snapshot
+/// without copying any pixels.
+pub struct VisionFrame<'a>
+{
+  atlas: &'a RgbaImage,
+  params: ViewParams,
+  pub frame_id: u64,
+}
+
+
+impl<'a> VisionFrame<'a>
+{
+  pub fn dimensions(&self) -> (u32, u32)
+  {
+    (self.params.width, self.params.height)
+  }
+
+  /// RGBA bytes of row `y`, which has to be below the frame height.
+  pub fn row(&self, y: u32) -> &'a [u8]
+  {
+    let row_bytes = self.params.width as usize * 4;
+    let start = ((self.params.y + y) as usize * self.atlas.width() as usize + self.params.x as usize) * 4;
+    &self.atlas.as_raw()[start..start + row_bytes]
+  }
+
+  pub fn pixel(&self, x: u32, y: u32) -> [u8; 4]
+  {
+    self.atlas.get_pixel(self.params.x + x, self.params.y + y).0
+  }
+
+  pub fn to_image(&self) -> RgbaImage
+  {
+    self.atlas.view(self.params.x, self.params.y, self.params.width, self.params.height).to_image()
+  }
+}
+
+
 impl<'w, 's> VisionView<'w, 's>
 {
-  pub fn get_view(&self, params: &ViewParams) -> (ImageBuffer<Rgba<u8>, Vec<u8>>, u64)
+  /// Part of the latest frame of `vision`'s target covered by `params`.
+  /// `None` until a frame covering it has been exported.
+  pub fn frame(&self, vision: &Vision, params: &ViewParams) -> Option<VisionFrame<'_>>
   {
-    let locked_images = self.exported_images.0.lock();
-    if let Some(image) = &locked_images.get(VISION)
-    {
-      let image = &image.0.read();
-      (image.img_buffer.view(params.x, params.y, params.width, params.height).to_image(), image.frame_id)
-    }
-    else
-    {
-      (ImageBuffer::new(1, 1), 0)
-    }
+    self.frame_at(vision, params, 0)
+  }
+
+  /// Like `frame`, but `age` frames back in the history kept for
+  /// `Vision::stack`. `None` if that frame hasn't been exported.
+  pub fn frame_at(&self, vision: &Vision, params: &ViewParams, age: usize) -> Option<VisionFrame<'_>>
+  {
+    let image = self.snapshot.0.get(self.targets.resolve(vision))?.iter().rev().nth(age)?;
+    let atlas = image.img_buffer.as_rgba8()?;
+
+    let fits = params.x + params.width <= atlas.width() && params.y + params.height <= atlas.height();
+    fits.then(|| VisionFrame { atlas, params: params.clone(), frame_id: image.frame_id })
+  }
+
+  /// Copy of the part of the latest frame of `vision`'s target covered by
+  /// `params`, with its frame id. `None` until a frame covering it has been
+  /// exported.
+  pub fn get_view(&self, vision: &Vision, params: &ViewParams) -> Option<(ImageBuffer<Rgba<u8>, Vec<u8>>, u64)>
+  {
+    self.frame(vision, params).map(|frame| (frame.to_image(), frame.frame_id))
+  }
+
+  /// Everything `vision` currently sees. `None` while its camera has no
+  /// viewport yet or no frame has been exported.
+  pub fn get_full_view(&self, vision: &Vision) -> Option<(ImageBuffer<Rgba<u8>, Vec<u8>>, u64)>
+  {
+    self.get_view(vision, vision.visual_sensor.as_ref()?)
   }
 }
 
 
+fn snapshot_vision(exported_images: Res<ExportedImages>,
+                   targets: Res<VisionTargets>,
+                   mut snapshot: ResMut<VisionSnapshot>,
+)
+{
+  snapshot.0 = targets.0.iter()
+    .map(|target_name| (target_name.clone(), exported_images.history(target_name).unwrap_or_default()))
+    .collect();
+}
+
+
+/// Camera based sensor. Agents may carry more than one, each on its own entity
+/// (the agent or its children), as long as every vision has a distinct `id`;
+/// the id orders both the viewports in the vision atlas and the agent's
+/// sensations.
 #[derive(Component, Debug, Default, Clone)]
 pub struct Vision
 {
   pub id: isize,
+  /// Name of the `VisionPlugin` target to render into, the first one when
+  /// `None`. Takes effect when the vision is added.
+  pub target: Option<String>,
   pub cam_id: Option<Entity>,
-  pub selected_cam_id: Option<Entity>,
+  /// Camera showing this vision in a corner of the window while it is selected.
+  pub preview_id: Option<Entity>,
   pub visual_sensor: Option<ViewParams>,
+  pub sampling: VisionSampling,
+  /// Scale pixel values into [0.0, 1.0] instead of the raw [0.0, 255.0].
+  pub normalize: bool,
+  /// Distance between two eyes. When set, the vision gets a second camera
+  /// offset to the right, rendering into its own viewport, and sensations
+  /// contain the left eye's samples followed by the right eye's. Takes effect
+  /// when the vision atlas is laid out.
+  pub stereo: Option<f32>,
+  /// Camera and viewport of the right eye of a stereo vision. `cam_id` and
+  /// `visual_sensor` belong to the left one.
+  pub right_cam_id: Option<Entity>,
+  pub right_sensor: Option<ViewParams>,
+  /// Number of consecutive frames whose samples are concatenated, oldest
+  /// first, so brains can perceive motion. Frames not exported yet are zeros.
+  /// 0 and 1 both mean only the latest frame. Takes effect when the vision
+  /// atlas is laid out, i.e. when the vision is added.
+  pub stack: usize,
+}
+
+
+/// Part of the vision image that is turned into sensations. Every sampled
+/// pixel contributes its RGBA channels, except for `Edges`.
+#[derive(Debug, Clone)]
+pub enum VisionSampling
+{
+  /// A single pixel row.
+  Row(u32),
+  /// Several pixel rows, concatenated in the given order.
+  Rows(Vec<u32>),
+  /// The full frame, keeping every n-th pixel of every n-th row.
+  Downsampled(u32),
+  /// The full frame, averaging every n by n block of pixels into one. Blocks
+  /// at the right and bottom edges are cut short by the frame.
+  Pooled(u32),
+  /// Sobel edge magnitude of the frame's brightness, averaged over n by n
+  /// blocks like `Pooled`. Gives one value per block instead of four.
+  Edges(u32),
+}
+
+
+impl Default for VisionSampling
+{
+  fn default() -> Self
+  {
+    VisionSampling::Row(25)
+  }
+}
+
+
+impl VisionSampling
+{
+  /// Samples the frame, clamping rows that fall outside of it to the last
+  /// row. Returns nothing only for an empty frame.
+  pub fn sample(&self, frame: &VisionFrame) -> Vec<u8>
+  {
+    let (width, height) = frame.dimensions();
+    if width == 0 || height == 0
+    {
+      return vec![];
+    }
+
+    let row = |y: u32| frame.row(y.min(height - 1));
+
+    match self
+    {
+      VisionSampling::Row(y) => row(*y).to_vec(),
+      VisionSampling::Rows(rows) => rows.iter().flat_map(|&y| row(y).iter().copied()).collect(),
+      VisionSampling::Downsampled(step) =>
+      {
+        let step = (*step).max(1);
+        (0..height).step_by(step as usize)
+          .flat_map(|y| (0..width).step_by(step as usize).map(move |x| (x, y)))
+          .flat_map(|(x, y)| frame.pixel(x, y))
+          .collect()
+      },
+      VisionSampling::Pooled(factor) =>
+      {
+        let factor = (*factor).max(1);
+        let mut pooled = Vec::with_capacity((width.div_ceil(factor) * height.div_ceil(factor) * 4) as usize);
+        for block_y in (0..height).step_by(factor as usize)
+        {
+          let rows = block_y..(block_y + factor).min(height);
+          for block_x in (0..width).step_by(factor as usize)
+          {
+            let columns = block_x as usize..(block_x + factor).min(width) as usize;
+            let mut sums = [0u32; 4];
+            for y in rows.clone()
+            {
+              for pixel in frame.row(y)[columns.start * 4..columns.end * 4].chunks_exact(4)
+              {
+                for (sum, &channel) in sums.iter_mut().zip(pixel)
+                {
+                  *sum += channel as u32;
+                }
+              }
+            }
+
+            let count = (rows.len() * columns.len()) as u32;
+            pooled.extend(sums.map(|sum| ((sum + count / 2) / count) as u8));
+          }
+        }
+        pooled
+      },
+      VisionSampling::Edges(factor) => edge_magnitudes(frame, (*factor).max(1)),
+    }
+  }
+}
+
+
+/// Sobel gradient magnitude of the frame's luma, scaled so the strongest
+/// edges saturate at 255, then averaged over `factor` by `factor` blocks.
+fn edge_magnitudes(frame: &VisionFrame, factor: u32) -> Vec<u8>
+{
+  let (width, height) = frame.dimensions();
+  let (width, height) = (width as usize, height as usize);
+
+  let mut luma = Vec::with_capacity(width * height);
+  for y in 0..height as u32
+  {
+    luma.extend(frame.row(y).chunks_exact(4)
+      .map(|pixel| 0.299 * pixel[0] as f32 + 0.587 * pixel[1] as f32 + 0.114 * pixel[2] as f32));
+  }
+
+  // Pixels outside of the frame repeat its border.
+  let at = |x: usize, y: usize, dx: isize, dy: isize|
+  {
+    let x = x.saturating_add_signed(dx).min(width - 1);
+    let y = y.saturating_add_signed(dy).min(height - 1);
+    luma[y * width + x]
+  };
+
+  let factor = factor as usize;
+  let mut edges = Vec::with_capacity(width.div_ceil(factor) * height.div_ceil(factor));
+  for block_y in (0..height).step_by(factor)
+  {
+    let rows = block_y..(block_y + factor).min(height);
+    for block_x in (0..width).step_by(factor)
+    {
+      let columns = block_x..(block_x + factor).min(width);
+      let mut sum = 0.0;
+      for y in rows.clone()
+      {
+        for x in columns.clone()
+        {
+          let gx = at(x, y, 1, -1) + 2.0 * at(x, y, 1, 0) + at(x, y, 1, 1)
+                 - at(x, y, -1, -1) - 2.0 * at(x, y, -1, 0) - at(x, y, -1, 1);
+          let gy = at(x, y, -1, 1) + 2.0 * at(x, y, 0, 1) + at(x, y, 1, 1)
+                 - at(x, y, -1, -1) - 2.0 * at(x, y, 0, -1) - at(x, y, 1, -1);
+          sum += (gx * gx + gy * gy).sqrt() / 4.0;
+        }
+      }
+
+      let mean = sum / (rows.len() * columns.len()) as f32;
+      edges.push(mean.round().min(255.0) as u8);
+    }
+  }
+  edges
 }
 
 
@@ -70,6 +349,89 @@ pub struct Vision
 pub struct VisionSensing;
 
 
+/// Resolution and projection of newly added vision cameras. Changing it does
+/// not affect cameras that already exist.
+#[derive(Resource, Debug, Clone)]
+pub struct VisionConfig
+{
+  pub width: u32,
+  pub height: u32,
+  /// Vertical field of view in radians.
+  pub fov: f32,
+  /// Mounting point of perspective cameras, in the local space of the entity
+  /// carrying the vision.
+  pub offset: Vec3,
+  /// Direction perspective cameras look in from `offset`, in the same space.
+  pub look_direction: Vec3,
+  /// Near plane of perspective cameras.
+  pub near: f32,
+  pub far: f32,
+  /// Background of the vision atlas, cleared once for all viewports.
+  pub clear_color: Color,
+  pub projection: VisionProjection,
+}
+
+
+/// How vision cameras look at the world. Distances are in the local space of
+/// the entity carrying the vision, so they scale with it.
+#[derive(Debug, Clone, Default)]
+pub enum VisionProjection
+{
+  /// Looking ahead through a perspective camera with `VisionConfig::fov`.
+  #[default]
+  Perspective,
+  /// Radar like orthographic view from `altitude` above the vision, looking
+  /// down with its forward direction pointing up in the image. `extent` is
+  /// the height of the area seen.
+  TopDown { altitude: f32, extent: f32 },
+}
+
+
+impl Default for VisionConfig
+{
+  fn default() -> Self
+  {
+    Self
+    {
+      width: 200,
+      height: 50,
+      fov: std::f32::consts::PI / 4.0,
+      offset: Vec3::new(0.0, -1.0, -7.0),
+      look_direction: Vec3::NEG_Z,
+      near: 0.1,
+      far: 500.0,
+      clear_color: Color::BLACK,
+      projection: VisionProjection::default(),
+    }
+  }
+}
+
+
+/// Debugging aid writing what every agent sees to
+/// `{dir}/ai_agent_{vision id}_{frame id}.png` while `save_frames` is set.
+/// Frames go through the `ImageSaver` queue and are dropped rather than
+/// stalling the simulation when it can't keep up.
+#[derive(Resource, Debug, Clone)]
+pub struct VisionDebugConfig
+{
+  pub save_frames: bool,
+  pub dir: String,
+}
+
+
+impl Default for VisionDebugConfig
+{
+  fn default() -> Self
+  {
+    Self
+    {
+      save_frames: false,
+      dir: "/tmp/vision".into(),
+    }
+  }
+}
+
+
 #[derive(Component, Debug)]
 pub struct VisionCam;
 
@@ -100,34 +462,98 @@ impl VisionObjectBundle
   pub fn new(id: isize) -> Self
   {
     let mut default = VisionObjectBundle::default();
-    match default.vision
+    if let Sensor::Vision(ref mut vision) = default.vision
     {
-      Sensor::Vision(ref mut vision) =>
-      {
-        vision.id = id;
-      },
+      vision.id = id;
     }
     default
   }
 }
 
 
-pub struct VisionPlugin;
+/// Render target image currently holding the visions of each target.
+#[derive(Resource, Debug, Default)]
+struct VisionAtlases(HashMap<String, Handle<Image>>);
+
+
+/// Renders visions into the target `target_name`, the key of their frames in
+/// `ExportedImages`. Several instances with different names can be added,
+/// e.g. one for the agents and one for a spectator overlay, each laying out
+/// the visions that name it in `Vision::target` in its own atlas. They share
+/// the `VisionConfig` and the selection and debugging systems.
+pub struct VisionPlugin
+{
+  /// Render target name, has to differ from every other exported target.
+  pub target_name: String,
+}
+
+
+impl Default for VisionPlugin
+{
+  fn default() -> Self
+  {
+    Self::named(DEFAULT_VISION_TARGET)
+  }
+}
+
+
+impl VisionPlugin
+{
+  pub fn named<S: Into<String>>(target_name: S) -> Self
+  {
+    Self { target_name: target_name.into() }
+  }
+}
 
 
 impl Plugin for VisionPlugin
 {
   fn build(&self, app: &mut App)
   {
+    let first_instance = !app.world.contains_resource::<VisionTargets>();
+    let mut targets = app.world.get_resource_or_insert_with(VisionTargets::default);
+    if targets.contains(&self.target_name)
+    {
+      panic!("A VisionPlugin rendering into {} was already added", self.target_name);
+    }
+    targets.0.push(self.target_name.clone());
+
+    // The systems serve all targets, they only need to be added once.
+    if !first_instance
+    {
+      return;
+    }
+
     app.add_systems(
       Update,
-      (make_pickable, draw_selected_vision, add_vision)
+      (make_pickable, sync_vision_twins, add_vision)
         .chain()
         .in_set(InGameSet::EntityUpdates),
     )
+    // Keeps working while paused, to inspect a frozen agent's view.
+    .add_systems(Update, draw_selected_vision)
     .add_systems(Update, handle_vision_selection.run_if(on_event::<VisionSelected>()))
+    .add_systems(Update, deselect_on_empty_click)
+    .add_systems(Update, update_vision_previews.after(handle_vision_selection))
+    .add_systems(
+      Update,
+      save_vision_frames
+        .run_if(|config: Res<VisionDebugConfig>| config.save_frames)
+        .after(InGameSet::EntityUpdates),
+    )
+    .add_systems(PreUpdate, snapshot_vision)
+    .init_resource::<VisionSnapshot>()
+    .init_resource::<VisionAtlases>()
+    .init_resource::<VisionConfig>()
+    .init_resource::<VisionDebugConfig>()
+    .init_resource::<ImageSaver>()
     .add_event::<VisionSelected>();
   }
+
+  fn is_unique(&self) -> bool
+  {
+    false
+  }
 }
 
 
@@ -143,103 +569,264 @@ impl From<ListenerInput<Pointer<Click>>> for VisionSelected
 }
 
 
+/// Lays out the visions of every target in one render target per target.
+/// Whenever new visions show up, e.g. for the next generation, the render
+/// target they go to is recreated for every live vision of that target, and
+/// its cameras and `ViewParams` move to their new viewports.
+#[allow(clippy::too_many_arguments)]
 fn add_vision(mut images: ResMut<Assets<Image>>,
-              mut visions: Query<(Entity, &mut Sensor), (With<Sensor>, Without<VisionSensing>)>,
+              mut sensors: Query<(Entity, &mut Sensor, Has<VisionSensing>)>,
+              mut vision_cams: Query<&mut Camera, With<VisionCam>>,
               mut commands: Commands,
               mut export_sources: ResMut<Assets<ImageSource>>,
               mut exported_images: ResMut<ExportedImages>,
+              export_bundles: Query<(Entity, &ImageExportSettings, &Handle<ImageSource>)>,
+              vision_config: Res<VisionConfig>,
+              targets: Res<VisionTargets>,
+              mut atlases: ResMut<VisionAtlases>,
 )
 {
-  if visions.is_empty()
+  // Other sensors share the component, only vision needs a render target.
+  let mut changed_targets: Vec<String> = vec![];
+  for (_, sensor, sensing) in sensors.iter()
   {
-    return;
-  }
+    let (Sensor::Vision(vision), false) = (sensor, sensing) else { continue; };
+    if let Some(target_name) = vision.target.as_deref().filter(|target_name| !targets.contains(target_name))
+    {
+      error!("Vision {} renders into {}, which no VisionPlugin provides, using {}", vision.id, target_name, targets.primary());
+    }
 
-  let viewport_size = (200, 50);
-  let (render_target, viewports) = gpu_copy::setup_render_target(
-    &VISION.to_string(),
-    &mut commands,
-    &mut images,
-    &mut export_sources,
-    &mut exported_images,
-    viewport_size,
-    visions.iter().count() as u32,
-  );
+    let target_name = targets.resolve(vision);
+    if !changed_targets.iter().any(|changed| changed == target_name)
+    {
+      changed_targets.push(target_name.to_string());
+    }
+  }
 
-  let mut clear_color = Some(ClearColorConfig::Custom(Color::rgb(0.0, 0.0, 0.0)));
-  for ((vision_id, mut sensor), viewport_pos) in visions.iter_mut().zip(viewports.iter())
+  for target_name in changed_targets
   {
-    match *sensor
+    let mut visions: Vec<_> = sensors.iter_mut()
+      .filter_map(|(entity, sensor, _)| match sensor.into_inner()
+      {
+        Sensor::Vision(vision) if targets.resolve(vision) == target_name => Some((entity, vision)),
+        _ => None,
+      })
+      .collect();
+
+    // Keep the atlas layout stable across re-layouts.
+    visions.sort_by_key(|(_, vision)| vision.id);
+
+    if let Some(old_image) = atlases.0.remove(&target_name)
+    {
+      gpu_copy::remove_render_target(&target_name,
+                                     &mut commands,
+                                     &export_bundles,
+                                     &mut export_sources,
+                                     &mut exported_images);
+      images.remove(&old_image);
+    }
+
+    let viewport_size = (vision_config.width, vision_config.height);
+    let history = visions.iter().map(|(_, vision)| vision.stack).max().unwrap_or(1);
+    // Stereo visions take two adjacent viewports, left eye first.
+    let num_views = visions.iter().map(|(_, vision)| if vision.stereo.is_some() { 2 } else { 1 }).sum::<u32>();
+    let (render_target, layout) = gpu_copy::setup_render_target_with_config(
+      ImageExportSettings::new(target_name.clone()),
+      &mut commands,
+      &mut images,
+      &mut export_sources,
+      &mut exported_images,
+      viewport_size,
+      num_views,
+      RenderTargetConfig::default()
+        .with_clear_color(vision_config.clear_color)
+        .with_history(history),
+    ).expect("Failed to set up the vision render target");
+
+    if let RenderTarget::Image(ref image) = render_target
+    {
+      atlases.0.insert(target_name.clone(), image.clone());
+    }
+
+    let mut slots = layout.viewports.iter().zip(0..);
+    let mut place_eye = |cam_id: &mut Option<Entity>, x_offset: f32, commands: &mut Commands| -> Option<ViewParams>
     {
-      Sensor::Vision(ref mut vision) =>
+      let (viewport_pos, slot) = slots.next()?;
+      let viewport = layout.render_viewport(slot as usize, viewport_size);
+      // Every camera of a target gets its own order, all of them below the
+      // main camera's.
+      let order = slot - num_views as isize;
+
+      if let Some(mut camera) = cam_id.and_then(|cam_id| vision_cams.get_mut(cam_id).ok())
       {
-        info!("Adding vision to id: {}", vision.id);
+        camera.target = render_target.clone();
+        camera.viewport = viewport;
+        camera.order = order;
+      }
+      else
+      {
+        *cam_id = Some(spawn_vision_camera(commands, &vision_config, render_target.clone(), viewport, order, x_offset));
+      }
 
-        vision.visual_sensor = Some(ViewParams
-        {
-          x: viewport_pos.0,
-          y: viewport_pos.1,
-          width: viewport_size.0,
-          height: viewport_size.1,
-        });
+      Some(ViewParams
+      {
+        x: viewport_pos.0,
+        y: viewport_pos.1,
+        width: viewport_size.0,
+        height: viewport_size.1,
+      })
+    };
+
+    for (vision_id, vision) in visions
+    {
+      if vision.cam_id.is_none()
+      {
+        info!("Adding vision to id: {} in {}", vision.id, target_name);
+      }
 
-        let current_cc = match clear_color.take()
-        {
-          Some(cc) => cc,
-          None => ClearColorConfig::None
-        };
+      let eye_offset = vision.stereo.map_or(0.0, |separation| separation / 2.0);
+      vision.visual_sensor = place_eye(&mut vision.cam_id, -eye_offset, &mut commands);
 
-        let camera_id = commands.spawn((Camera3dBundle
-        {
-          camera_3d: Camera3d
-          {
-            ..default()
-          },
-          camera: Camera
-          {
-            clear_color: current_cc,
-            // render before the "main pass" camera
-            order: vision.id,
-            target: render_target.clone(),
-            viewport: Some(Viewport {
-              physical_position: UVec2::new(viewport_pos.0, viewport_pos.1),
-              physical_size: UVec2::new(viewport_size.0, viewport_size.1),
-              ..default()
-            }),
-            ..default()
-          },
-          transform: Transform::from_translation(Vec3::new(0.0, -1.0, -7.0))
-              .looking_at(Vec3::new(0.0, -1.0, -30.), Vec3::Y),
-          projection: PerspectiveProjection
-          {
-            far: 500.0,
-            ..default()
-          }.into(),
-          ..default()
-        },
-        )).id();
-
-        vision.cam_id = Some(camera_id);
-
-        commands.entity(camera_id).insert(VisionCam{});
-        commands.entity(vision_id).push_children(&[camera_id]);
-        commands.entity(vision_id).insert(VisionSensing{});
+      if vision.stereo.is_some()
+      {
+        vision.right_sensor = place_eye(&mut vision.right_cam_id, eye_offset, &mut commands);
       }
+      else if let Some(right_cam_id) = vision.right_cam_id.take()
+      {
+        vision.right_sensor = None;
+        commands.entity(right_cam_id).despawn_recursive();
+      }
+
+      let cameras: Vec<_> = vision.cam_id.iter().chain(vision.right_cam_id.iter()).copied().collect();
+      commands.entity(vision_id).push_children(&cameras);
+      commands.entity(vision_id).insert(VisionSensing{});
     }
   }
 }
 
 
+/// Spawns a vision camera, `x_offset` to the right of the vision's center.
+fn spawn_vision_camera(commands: &mut Commands,
+                       vision_config: &VisionConfig,
+                       target: RenderTarget,
+                       viewport: Option<Viewport>,
+                       order: isize,
+                       x_offset: f32,
+) -> Entity
+{
+  let (transform, projection) = match vision_config.projection
+  {
+    VisionProjection::Perspective =>
+    (
+      Transform::from_translation(vision_config.offset + Vec3::X * x_offset)
+        .looking_to(vision_config.look_direction, Vec3::Y),
+      PerspectiveProjection
+      {
+        fov: vision_config.fov,
+        near: vision_config.near,
+        far: vision_config.far,
+        ..default()
+      }.into(),
+    ),
+    VisionProjection::TopDown { altitude, extent } =>
+    (
+      Transform::from_translation(Vec3::new(x_offset, altitude, 0.0))
+        .looking_at(Vec3::new(x_offset, 0.0, 0.0), Vec3::NEG_Z),
+      OrthographicProjection
+      {
+        scaling_mode: ScalingMode::FixedVertical(extent),
+        far: vision_config.far,
+        ..default()
+      }.into(),
+    ),
+  };
+
+  commands.spawn((Camera3dBundle
+  {
+    camera_3d: Camera3d
+    {
+      ..default()
+    },
+    camera: Camera
+    {
+      // The atlas is cleared once for all viewports by gpu_copy.
+      clear_color: ClearColorConfig::None,
+      order,
+      target,
+      viewport,
+      ..default()
+    },
+    transform,
+    projection,
+    ..default()
+  },
+  VisionCam{},
+  RenderLayers::layer(VISION_LAYER),
+  )).id()
+}
+
+
+/// Copy of a scene mesh drawn only for the vision cameras. It keeps the
+/// mesh's untinted material, so agents don't see the selection highlight.
+#[derive(Component, Debug)]
+struct VisionTwin;
+
+
+/// Makes scene meshes selectable and visible to the vision cameras. Meshes
+/// that already have `RenderLayers` were placed by their feature, e.g. the
+/// minimap markers, and are left alone.
 fn make_pickable(mut commands: Commands,
-                 meshes: Query<Entity, (With<Handle<Mesh>>, Without<Pickable>)>,
+                 meshes: Query<(Entity, &Handle<Mesh>, Option<&Handle<StandardMaterial>>),
+                               (Without<Pickable>, Without<RenderLayers>)>,
 )
 {
-  for entity in meshes.iter()
+  for (entity, mesh, material) in meshes.iter()
   {
     commands
       .entity(entity)
-      .insert((PickableBundle::default(), HIGHLIGHT_TINT.clone()))
-      .insert(RenderLayers::all());
+      .insert((PickableBundle::default(), HIGHLIGHT_TINT.clone()));
+
+    // The highlight only swaps standard materials, other meshes can be seen
+    // as they are.
+    let Some(material) = material else
+    {
+      commands.entity(entity).insert(RenderLayers::from_layers(&[0, VISION_LAYER]));
+      continue;
+    };
+
+    let twin = commands.spawn((
+      PbrBundle
+      {
+        mesh: mesh.clone(),
+        material: material.clone(),
+        ..default()
+      },
+      RenderLayers::layer(VISION_LAYER),
+      // The mesh itself already casts the shadow.
+      bevy::pbr::NotShadowCaster,
+      Pickable::IGNORE,
+      VisionTwin,
+    )).id();
+    commands.entity(entity).add_child(twin);
+  }
+}
+
+
+/// Keeps twins on the material their mesh has when it isn't highlighted.
+fn sync_vision_twins(meshes: Query<(&InitialHighlight<StandardMaterial>, &Children), Changed<InitialHighlight<StandardMaterial>>>,
+                     mut twins: Query<&mut Handle<StandardMaterial>, With<VisionTwin>>,
+)
+{
+  for (initial, children) in meshes.iter()
+  {
+    let mut child_twins = twins.iter_many_mut(children);
+    while let Some(mut material) = child_twins.fetch_next()
+    {
+      if *material != initial.initial
+      {
+        *material = initial.initial.clone();
+      }
+    }
   }
 }
 
@@ -263,52 +850,137 @@ const HIGHLIGHT_TINT: Highlight<StandardMaterial> = Highlight
 };
 
 
-fn attach_vision_camera(commands: &mut Commands,
-                        vision_id: Entity,
-                        vision: &Vision) -> Entity
+/// Logical width of the preview of a selected vision.
+const PREVIEW_WIDTH: f32 = 256.0;
+/// Previews render after the main camera, on top of its view.
+const PREVIEW_ORDER: isize = 1;
+
+
+/// Camera showing what a selected vision sees in the top left corner of the
+/// window. It is a child of the vision's camera, so it sees the same.
+#[derive(Component, Debug)]
+struct VisionPreview
 {
-  let camera_id = commands.spawn((Camera3dBundle
-  {
-    camera_3d: Camera3d
+  /// Height over width of the previewed vision.
+  aspect: f32,
+}
+
+
+/// UI node drawn by the main camera behind a `VisionPreview`. Camera clears
+/// cover the whole window, so the preview can't clear its corner itself.
+#[derive(Component, Debug)]
+struct PreviewBackdrop
+{
+  preview: Entity,
+}
+
+
+/// Viewport and logical size of a preview in `window`.
+fn preview_rect(window: &Window, aspect: f32) -> (Viewport, Vec2)
+{
+  let width = PREVIEW_WIDTH.min(window.width());
+  let size = Vec2::new(width, width * aspect);
+  let physical_size = (size * window.scale_factor()).as_uvec2()
+    .clamp(UVec2::ONE, UVec2::new(window.physical_width(), window.physical_height()).max(UVec2::ONE));
+
+  (Viewport { physical_position: UVec2::ZERO, physical_size, ..default() }, size)
+}
+
+
+/// Splits the window: the main view keeps rendering full size while a camera
+/// sharing the vision camera's projection draws into the top left corner.
+fn attach_vision_preview(commands: &mut Commands,
+                         vision: &Vision,
+                         vision_cams: &Query<&Projection, With<VisionCam>>,
+                         windows: &Query<&Window, With<PrimaryWindow>>,
+) -> Option<Entity>
+{
+  let window = windows.get_single().ok()?;
+  let view_params = vision.visual_sensor.as_ref()?;
+  let cam_id = vision.cam_id?;
+  let projection = vision_cams.get(cam_id).ok()?;
+
+  let aspect = view_params.height as f32 / view_params.width.max(1) as f32;
+  let (viewport, size) = preview_rect(window, aspect);
+
+  let preview_id = commands.spawn((
+    Camera3dBundle
     {
+      camera: Camera
+      {
+        order: PREVIEW_ORDER,
+        target: RenderTarget::Window(WindowRef::Primary),
+        viewport: Some(viewport),
+        clear_color: ClearColorConfig::None,
+        ..default()
+      },
+      projection: projection.clone(),
       ..default()
     },
-    camera: Camera
+    RenderLayers::layer(VISION_LAYER),
+    VisionPreview { aspect },
+  )).id();
+  commands.entity(cam_id).add_child(preview_id);
+
+  commands.spawn((
+    NodeBundle
     {
-      clear_color: ClearColorConfig::None,
-      // render before the "main pass" camera
-      order: vision.id,
-      viewport: Some(Viewport {
-        physical_position: UVec2::new(0, 0),
-        physical_size: UVec2::new(256, 256),
+      style: Style
+      {
+        position_type: PositionType::Absolute,
+        left: Val::Px(0.0),
+        top: Val::Px(0.0),
+        width: Val::Px(size.x),
+        height: Val::Px(size.y),
         ..default()
-      }),
+      },
+      background_color: Color::BLACK.into(),
       ..default()
     },
-    transform: Transform::from_translation(Vec3::new(0.0, -1.0, -7.0))
-        .looking_at(Vec3::new(0.0, -1.0, -30.), Vec3::Y),
-    projection: PerspectiveProjection
-    {
-      far: 500.0,
-      ..default()
-    }.into(),
-    ..default()
-  },
-  )).id();
+    PreviewBackdrop { preview: preview_id },
+  ));
+
+  Some(preview_id)
+}
 
-  commands.entity(vision_id).push_children(&[camera_id]);
 
-  camera_id
+fn detach_vision_preview(preview: Option<Entity>,
+                         commands: &mut Commands,
+)
+{
+  // The backdrop follows in `update_vision_previews`.
+  if let Some(preview_id) = preview
+  {
+    commands.entity(preview_id).despawn_recursive();
+  }
 }
 
 
-fn detach_vision_camera(selected_cam: Option<Entity>,
-                        commands: &mut Commands,
+/// Keeps previews in their corner when the window is resized and removes the
+/// backdrops of previews that are gone, e.g. with the spaceship they were on.
+fn update_vision_previews(mut commands: Commands,
+                          mut previews: Query<(&mut Camera, &VisionPreview)>,
+                          mut backdrops: Query<(Entity, &PreviewBackdrop, &mut Style)>,
+                          windows: Query<&Window, With<PrimaryWindow>>,
+                          mut resized: EventReader<WindowResized>,
 )
 {
-  if let Some(cam_id) = selected_cam
+  let window_resized = resized.read().count() > 0;
+  for (backdrop_id, backdrop, mut style) in backdrops.iter_mut()
   {
-    commands.entity(cam_id).despawn_recursive();
+    let Ok((mut camera, preview)) = previews.get_mut(backdrop.preview) else
+    {
+      commands.entity(backdrop_id).despawn_recursive();
+      continue;
+    };
+
+    if let (true, Ok(window)) = (window_resized, windows.get_single())
+    {
+      let (viewport, size) = preview_rect(window, preview.aspect);
+      camera.viewport = Some(viewport);
+      style.width = Val::Px(size.x);
+      style.height = Val::Px(size.y);
+    }
   }
 }
 
@@ -321,33 +993,63 @@ fn unselect_vision(selected_vision: Entity,
 }
 
 
+type SelectedVisions<'w, 's> = Query<'w, 's, (Entity, &'static mut Sensor), (With<Sensor>, With<PickSelection>)>;
+
+
+/// Unselects every selected vision and returns the ones that were selected.
+fn clear_vision_selection(selected_visions: &mut SelectedVisions,
+                          commands: &mut Commands,
+) -> Vec<Entity>
+{
+  let mut cleared = vec![];
+  for (selected_vision, mut vision) in selected_visions.iter_mut()
+  {
+    if let Sensor::Vision(ref mut vision) = *vision
+    {
+      detach_vision_preview(vision.preview_id.take(), commands);
+      unselect_vision(selected_vision, commands);
+      cleared.push(selected_vision);
+    }
+  }
+  cleared
+}
+
+
+/// Clicking anywhere that is not a vision clears the current selection.
+fn deselect_on_empty_click(mouse: Res<ButtonInput<MouseButton>>,
+                           mut selected: EventReader<VisionSelected>,
+                           mut selected_visions: SelectedVisions,
+                           mut commands: Commands,
+)
+{
+  let clicked_vision = selected.read().count() > 0;
+  if mouse.just_released(MouseButton::Left) && !clicked_vision
+  {
+    clear_vision_selection(&mut selected_visions, &mut commands);
+  }
+}
+
+
 fn handle_vision_selection(mut selected: EventReader<VisionSelected>,
                            mut params: ParamSet<(
                                Query<(Entity, &mut Sensor), With<Sensor>>,
-                               Query<(Entity, &Sensor), (With<Sensor>, With<PickSelection>)>
+                               SelectedVisions,
                            )>,
+                           vision_cams: Query<&Projection, With<VisionCam>>,
+                           windows: Query<&Window, With<PrimaryWindow>>,
                            mut commands: Commands,
 )
 {
-  {
-    let already_selected_query = params.p1();
+  let previously_selected = clear_vision_selection(&mut params.p1(), &mut commands);
 
-    if !already_selected_query.is_empty()
+  for VisionSelected(selected_vision_id) in selected.read()
+  {
+    // Clicking the selected vision again toggles it off.
+    if previously_selected.contains(selected_vision_id)
     {
-      let (selected_vision, vision) = already_selected_query.single();
-      match vision
-      {
-        Sensor::Vision(ref vision) =>
-        {
-          detach_vision_camera(vision.selected_cam_id, &mut commands);
-          unselect_vision(selected_vision, &mut commands);
-        }
-      }
+      return;
     }
-  }
 
-  for VisionSelected(selected_vision_id) in selected.read()
-  {
     let mut vision_query = params.p0();
     for (vision_id, mut vision) in vision_query.iter_mut()
     {
@@ -357,12 +1059,9 @@ fn handle_vision_selection(mut selected: EventReader<VisionSelected>,
           is_selected: true
         });
 
-        match *vision
+        if let Sensor::Vision(ref mut vision) = *vision
         {
-          Sensor::Vision(ref mut vision) =>
-          {
-            vision.selected_cam_id = Some(attach_vision_camera(&mut commands, vision_id, &vision));
-          }
+          vision.preview_id = attach_vision_preview(&mut commands, vision, &vision_cams, &windows);
         }
         return;
       }
@@ -371,9 +1070,66 @@ fn handle_vision_selection(mut selected: EventReader<VisionSelected>,
 }
 
 
+/// Saves what every vision sees, named after the vision id. Stereo visions
+/// save the right eye as well, with a `_right` suffix.
+fn save_vision_frames(sensors: Query<&Sensor>,
+                      vision_view: VisionView,
+                      config: Res<VisionDebugConfig>,
+                      image_saver: Res<ImageSaver>,
+                      mut frame_paths: Local<Option<FramePathTemplate>>,
+                      mut saved_frames: Local<HashMap<String, u64>>,
+)
+{
+  if config.is_changed() || frame_paths.is_none()
+  {
+    *frame_paths = match FramePathTemplate::new(&config.dir, VISION_FRAME_TEMPLATE)
+    {
+      Ok(frame_paths) => Some(frame_paths),
+      Err(e) =>
+      {
+        error!("Couldn't create the vision debug directory {} | {e:?}", config.dir);
+        None
+      },
+    };
+  }
+  let Some(frame_paths) = frame_paths.as_ref() else { return; };
+
+  for sensor in sensors.iter()
+  {
+    let Sensor::Vision(vision) = sensor else { continue; };
+    let eyes = [
+      (vision.id.to_string(), vision.visual_sensor.as_ref()),
+      (format!("{}_right", vision.id), vision.right_sensor.as_ref()),
+    ];
+
+    for (name, params) in eyes
+    {
+      let Some((image, frame_id)) = params.and_then(|params| vision_view.get_view(vision, params)) else { continue; };
+
+      // Frame 0 means nothing was rendered yet, and every frame is saved once.
+      if frame_id == 0 || saved_frames.get(&name) == Some(&frame_id)
+      {
+        continue;
+      }
+
+      let path = frame_paths.path(&name, frame_id, "png");
+      saved_frames.insert(name, frame_id);
+
+      let frame = ImageWrapper
+      {
+        img_buffer: DynamicImage::ImageRgba8(image),
+        format: TextureFormat::Rgba8UnormSrgb,
+        frame_id,
+      };
+      image_saver.save(path, Arc::new(frame));
+    }
+  }
+}
+
+
 fn draw_selected_vision(mut gizmos: Gizmos,
                         query_vision: Query<(Entity, &Children, &PickSelection), (With<Sensor>, With<PickSelection>)>,
-                        query_proj: Query<(&Projection, &GlobalTransform), Without<VisionCam>>)
+                        query_proj: Query<(&Projection, &GlobalTransform), With<VisionCam>>)
 {
   for (_vision, children, pick) in query_vision.iter()
   {
@@ -383,7 +1139,8 @@ fn draw_selected_vision(mut gizmos: Gizmos,
       {
         if let Ok((projection, &transform)) = query_proj.get(child)
         {
-          match projection
+          // Corners in camera space: top left, top right, bottom right, bottom left.
+          let (near, far) = match projection
           {
             Projection::Perspective(proj) =>
             {
@@ -394,43 +1151,162 @@ fn draw_selected_vision(mut gizmos: Gizmos,
               let far_height = 2.0 * tan_half_fov * proj.far;
               let far_width = far_height * proj.aspect_ratio;
 
-              // Near plane corners
-              let near_top_left = transform * Vec3::new(-near_width / 2.0, near_height / 2.0, -proj.near);
-              let near_top_right = transform * Vec3::new(near_width / 2.0, near_height / 2.0, -proj.near);
-              let near_bottom_left = transform * Vec3::new(-near_width / 2.0, -near_height / 2.0, -proj.near);
-              let near_bottom_right = transform * Vec3::new(near_width / 2.0, -near_height / 2.0, -proj.near);
-
-              // Far plane corners
-              let far_top_left = transform * Vec3::new(-far_width / 2.0, far_height / 2.0, -proj.far);
-              let far_top_right = transform * Vec3::new(far_width / 2.0, far_height / 2.0, -proj.far);
-              let far_bottom_left = transform * Vec3::new(-far_width / 2.0, -far_height / 2.0, -proj.far);
-              let far_bottom_right = transform * Vec3::new(far_width / 2.0, -far_height / 2.0, -proj.far);
-
-              // Draw lines between corners to form the frustum
-              let color = Color::rgba(0.0, 1.0, 0.0, 0.5); // Green, semi-transparent
-
-              // Near plane
-              gizmos.line(near_top_left, near_top_right, color);
-              gizmos.line(near_top_right, near_bottom_right, color);
-              gizmos.line(near_bottom_right, near_bottom_left, color);
-              gizmos.line(near_bottom_left, near_top_left, color);
-
-              // Far plane
-              gizmos.line(far_top_left, far_top_right, color);
-              gizmos.line(far_top_right, far_bottom_right, color);
-              gizmos.line(far_bottom_right, far_bottom_left, color);
-              gizmos.line(far_bottom_left, far_top_left, color);
-
-              // Edges between near and far planes
-              gizmos.line(near_top_left, far_top_left, color);
-              gizmos.line(near_top_right, far_top_right, color);
-              gizmos.line(near_bottom_left, far_bottom_left, color);
-              gizmos.line(near_bottom_right, far_bottom_right, color);
+              let near = Rect::from_center_size(Vec2::ZERO, Vec2::new(near_width, near_height));
+              let far = Rect::from_center_size(Vec2::ZERO, Vec2::new(far_width, far_height));
+              ((near, proj.near), (far, proj.far))
             },
-            _ => {}
+            Projection::Orthographic(proj) =>
+            {
+              // The box keeps the same cross section along the whole depth.
+              ((proj.area, proj.near), (proj.area, proj.far))
+            },
+          };
+
+          let plane_corners = |(rect, depth): (Rect, f32)| [
+            transform * Vec3::new(rect.min.x, rect.max.y, -depth),
+            transform * Vec3::new(rect.max.x, rect.max.y, -depth),
+            transform * Vec3::new(rect.max.x, rect.min.y, -depth),
+            transform * Vec3::new(rect.min.x, rect.min.y, -depth),
+          ];
+          let near_corners = plane_corners(near);
+          let far_corners = plane_corners(far);
+
+          // Draw lines between corners to form the frustum
+          let color = Color::rgba(0.0, 1.0, 0.0, 0.5); // Green, semi-transparent
+
+          for (corner, next) in (0..4).map(|corner| (corner, (corner + 1) % 4))
+          {
+            // Near plane
+            gizmos.line(near_corners[corner], near_corners[next], color);
+            // Far plane
+            gizmos.line(far_corners[corner], far_corners[next], color);
+            // Edges between near and far planes
+            gizmos.line(near_corners[corner], far_corners[corner], color);
           }
         }
       }
     }
   }
 }
+
+
+#[cfg(test)]
+mod tests
+{
+  use super::*;
+
+  fn camera_count(app: &mut App) -> usize
+  {
+    app.world.query::<&Camera>().iter(&app.world).count()
+  }
+
+  #[test]
+  fn vision_plugins_with_different_targets_coexist()
+  {
+    let mut app = App::new();
+    app.add_plugins((VisionPlugin::default(), VisionPlugin::named("Spectator")));
+
+    let targets = app.world.resource::<VisionTargets>();
+    assert_eq!(targets.0, [DEFAULT_VISION_TARGET, "Spectator"]);
+
+    let spectator = Vision { target: Some("Spectator".to_string()), ..default() };
+    let unknown = Vision { target: Some("Unknown".to_string()), ..default() };
+    assert_eq!(targets.resolve(&spectator), "Spectator");
+    assert_eq!(targets.resolve(&unknown), DEFAULT_VISION_TARGET);
+    assert_eq!(targets.resolve(&Vision::default()), DEFAULT_VISION_TARGET);
+  }
+
+  #[test]
+  fn reselecting_a_vision_keeps_the_camera_count()
+  {
+    let mut app = App::new();
+    app.add_event::<VisionSelected>()
+      .add_systems(Update, handle_vision_selection);
+
+    app.world.spawn((Window::default(), PrimaryWindow));
+    let cam_id = app.world.spawn((Camera3dBundle::default(), VisionCam)).id();
+    let vision_id = app.world.spawn(Sensor::Vision(Vision
+    {
+      cam_id: Some(cam_id),
+      visual_sensor: Some(ViewParams { x: 0, y: 0, width: 200, height: 50 }),
+      ..default()
+    })).id();
+
+    let before = camera_count(&mut app);
+    let mut counts = vec![];
+    // Select, deselect by clicking it again, then select once more.
+    for _ in 0..3
+    {
+      app.world.send_event(VisionSelected(vision_id));
+      app.update();
+      counts.push(camera_count(&mut app));
+    }
+
+    // Only the preview camera comes and goes.
+    assert_eq!(counts, vec![before + 1, before, before + 1]);
+  }
+
+  #[test]
+  fn saves_the_frames_of_both_eyes()
+  {
+    let dir = std::env::temp_dir().join(format!("vision_frames_{}", std::process::id()));
+    let atlas = RgbaImage::from_pixel(4, 1, Rgba([255, 0, 0, 255]));
+    let frame = ImageWrapper { img_buffer: DynamicImage::ImageRgba8(atlas), format: TextureFormat::Rgba8UnormSrgb, frame_id: 1, render_frame: 1 };
+
+    let mut app = App::new();
+    app.insert_resource(VisionSnapshot([(DEFAULT_VISION_TARGET.to_string(), vec![Arc::new(frame)])].into_iter().collect()))
+      .init_resource::<VisionTargets>()
+      .init_resource::<ImageSaver>()
+      .insert_resource(VisionDebugConfig { save_frames: true, dir: dir.to_string_lossy().into() })
+      .add_systems(Update, save_vision_frames);
+
+    app.world.spawn(Sensor::Vision(Vision
+    {
+      id: 3,
+      stereo: Some(1.0),
+      visual_sensor: Some(ViewParams { x: 0, y: 0, width: 2, height: 1 }),
+      right_sensor: Some(ViewParams { x: 2, y: 0, width: 2, height: 1 }),
+      ..default()
+    }));
+
+    app.update();
+    app.world.resource::<ImageSaver>().flush();
+
+    for name in ["3", "3_right"]
+    {
+      let path = dir.join(format!("ai_agent_{name}_000001.png"));
+      let saved = image::open(&path).unwrap_or_else(|e| panic!("{path:?} wasn't saved | {e}"));
+      assert_eq!(saved.dimensions(), (2, 1));
+    }
+    let _ = std::fs::remove_dir_all(&dir);
+  }
+
+  /// Sampling views in place in the snapshot atlas gives the same result as
+  /// sampling copies cropped out of it, like sensing did before snapshots.
+  #[test]
+  fn snapshot_sampling_matches_cropped_views()
+  {
+    let (width, height, columns) = (16, 12, 3);
+    let atlas = RgbaImage::from_fn(columns * width, 2 * height, |x, y| Rgba([(x * 7 + y * 13) as u8, (x * y) as u8, (x ^ y) as u8, 255]));
+    let samplings = [
+      VisionSampling::Row(5),
+      VisionSampling::Rows(vec![0, 7, 40]),
+      VisionSampling::Downsampled(3),
+      VisionSampling::Pooled(4),
+      VisionSampling::Edges(2),
+    ];
+
+    for view in 0..2 * columns
+    {
+      let params = ViewParams { x: view % columns * width, y: view / columns * height, width, height };
+      let crop = atlas.view(params.x, params.y, width, height).to_image();
+      let in_place = VisionFrame { atlas: &atlas, params: params.clone(), frame_id: 1 };
+      let cropped = VisionFrame { atlas: &crop, params: ViewParams { x: 0, y: 0, width, height }, frame_id: 1 };
+
+      for sampling in &samplings
+      {
+        assert_eq!(sampling.sample(&in_place), sampling.sample(&cropped), "{sampling:?} of view {view}");
+      }
+    }
+  }
+}
@@ -0,0 +1,160 @@
+use std::{
+  net::{TcpListener, TcpStream},
+  sync::{Arc, Mutex},
+  thread,
+};
+
+use bevy::prelude::*;
+use crossbeam_channel::{Receiver, Sender, TrySendError};
+use gpu_copy::{ExportedImages, ImageWrapper};
+use tungstenite::{Message, WebSocket};
+
+use crate::vision::VisionTargets;
+
+
+const DEFAULT_STREAM_ADDR: &str = "127.0.0.1:9001";
+
+
+/// Streams the atlas of the first vision target to websocket clients as WebP
+/// data URLs, ready to be used as an `<img>` source. Encoding and sending happen on their own
+/// threads, and frames are dropped for clients that can't keep up instead of
+/// stalling the simulation.
+pub struct VisionStreamPlugin
+{
+  pub addr: String,
+}
+
+
+impl Default for VisionStreamPlugin
+{
+  fn default() -> Self
+  {
+    Self { addr: DEFAULT_STREAM_ADDR.to_string() }
+  }
+}
+
+
+impl VisionStreamPlugin
+{
+  pub fn new(addr: impl Into<String>) -> Self
+  {
+    Self { addr: addr.into() }
+  }
+}
+
+
+type Clients = Arc<Mutex<Vec<Sender<Arc<String>>>>>;
+
+
+#[derive(Resource)]
+struct VisionStream
+{
+  frames: Sender<Arc<ImageWrapper>>,
+  clients: Clients,
+}
+
+
+impl Plugin for VisionStreamPlugin
+{
+  fn build(&self, app: &mut App)
+  {
+    let listener = match TcpListener::bind(&self.addr)
+    {
+      Ok(listener) => listener,
+      Err(err) =>
+      {
+        error!("Vision stream can't listen on {}: {err}", self.addr);
+        return;
+      },
+    };
+    info!("Streaming vision on ws://{}", self.addr);
+
+    let clients = Clients::default();
+    // Holds a single frame, newer ones are dropped while it is being encoded.
+    let (frames, frame_receiver) = crossbeam_channel::bounded(1);
+
+    let accepted = clients.clone();
+    thread::spawn(move || accept_clients(listener, accepted));
+    let encoded = clients.clone();
+    thread::spawn(move || encode_frames(frame_receiver, encoded));
+
+    app
+      .insert_resource(VisionStream { frames, clients })
+      .add_systems(Last, stream_vision_frames);
+  }
+}
+
+
+fn stream_vision_frames(stream: Res<VisionStream>,
+                        exported_images: Res<ExportedImages>,
+                        targets: Res<VisionTargets>,
+                        mut last_seen: Local<u64>,
+)
+{
+  if stream.clients.lock().unwrap().is_empty()
+  {
+    return;
+  }
+
+  if let Some(frame) = exported_images.frame_if_newer(targets.primary(), &mut last_seen)
+  {
+    // A full channel means the encoder is still busy, skip this frame.
+    let _ = stream.frames.try_send(frame);
+  }
+}
+
+
+fn accept_clients(listener: TcpListener, clients: Clients)
+{
+  for stream in listener.incoming()
+  {
+    let socket = match stream.map_err(|err| err.to_string())
+      .and_then(|stream| tungstenite::accept(stream).map_err(|err| err.to_string()))
+    {
+      Ok(socket) => socket,
+      Err(err) =>
+      {
+        warn!("Rejected vision stream client: {err}");
+        continue;
+      },
+    };
+
+    let (sender, receiver) = crossbeam_channel::bounded(1);
+    clients.lock().unwrap().push(sender);
+    thread::spawn(move || send_frames(socket, receiver));
+  }
+}
+
+
+fn encode_frames(frames: Receiver<Arc<ImageWrapper>>, clients: Clients)
+{
+  for frame in frames
+  {
+    let data_url = match frame.to_web_base64()
+    {
+      Ok(data_url) => Arc::new(data_url),
+      Err(err) =>
+      {
+        warn!("Failed to encode vision frame {}: {err}", frame.frame_id);
+        continue;
+      },
+    };
+
+    // Slow clients miss the frame, disconnected ones are forgotten.
+    clients.lock().unwrap().retain(|client|
+      !matches!(client.try_send(data_url.clone()), Err(TrySendError::Disconnected(_))));
+  }
+}
+
+
+fn send_frames(mut socket: WebSocket<TcpStream>, frames: Receiver<Arc<String>>)
+{
+  for data_url in frames
+  {
+    if let Err(err) = socket.send(Message::Text(data_url.to_string()))
+    {
+      info!("Vision stream client left: {err}");
+      return;
+    }
+  }
+}
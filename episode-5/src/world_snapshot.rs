@@ -0,0 +1,97 @@
+use bevy::{core::FrameCount, prelude::*};
+use gpu_copy::ImageSaver;
+use serde::Serialize;
+
+use crate::{fitness::Fitness, health::Health, movement::Velocity};
+
+
+/// Writes the state of every moving, damageable or scored entity as one line
+/// of JSON per frame to `path` while `enabled` is set. Lines go through the
+/// `ImageSaver` queue and are dropped rather than stalling the simulation.
+#[derive(Resource, Debug, Clone)]
+pub struct WorldSnapshotConfig
+{
+  pub enabled: bool,
+  pub path: String,
+}
+
+
+impl Default for WorldSnapshotConfig
+{
+  fn default() -> Self
+  {
+    Self
+    {
+      enabled: false,
+      path: "/tmp/world_snapshots.ndjson".to_string(),
+    }
+  }
+}
+
+
+#[derive(Serialize, Debug)]
+pub struct Snapshot
+{
+  pub frame: u64,
+  pub entities: Vec<EntityState>,
+}
+
+
+#[derive(Serialize, Debug)]
+pub struct EntityState
+{
+  pub entity: u64,
+  pub translation: [f32; 3],
+  pub rotation: [f32; 4],
+  pub velocity: Option<[f32; 3]>,
+  pub health: Option<f32>,
+  pub fitness: Option<f32>,
+}
+
+
+pub struct WorldSnapshotPlugin;
+
+
+impl Plugin for WorldSnapshotPlugin
+{
+  fn build(&self, app: &mut App)
+  {
+    app.add_systems(Last, write_world_snapshot.run_if(|config: Res<WorldSnapshotConfig>| config.enabled))
+      .init_resource::<WorldSnapshotConfig>()
+      .init_resource::<ImageSaver>();
+  }
+}
+
+
+fn write_world_snapshot(entities: Query<(Entity, &Transform, Option<&Velocity>, Option<&Health>, Option<&Fitness>),
+                                        Or<(With<Velocity>, With<Health>, With<Fitness>)>>,
+                        frame_count: Res<FrameCount>,
+                        config: Res<WorldSnapshotConfig>,
+                        image_saver: Res<ImageSaver>,
+)
+{
+  let snapshot = Snapshot
+  {
+    frame: frame_count.0 as u64,
+    entities: entities.iter()
+      .map(|(entity, transform, velocity, health, fitness)| EntityState
+      {
+        entity: entity.to_bits(),
+        translation: transform.translation.to_array(),
+        rotation: transform.rotation.to_array(),
+        velocity: velocity.map(|velocity| velocity.value.to_array()),
+        health: health.map(|health| health.value),
+        fitness: fitness.map(|fitness| fitness.0),
+      })
+      .collect(),
+  };
+
+  match serde_json::to_string(&snapshot)
+  {
+    Ok(line) =>
+    {
+      image_saver.append_line(&config.path, line);
+    },
+    Err(e) => error!("Couldn't serialize the world snapshot of frame {} | {e:?}", snapshot.frame),
+  }
+}
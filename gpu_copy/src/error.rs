@@ -0,0 +1,52 @@
+use std::fmt;
+
+use bevy::render::render_resource::TextureFormat;
+
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ExportError
+{
+  /// The source image has not been uploaded to the GPU yet.
+  SourceImageMissing,
+  /// The texture format can't be copied into a CPU buffer row by row, e.g.
+  /// compressed or depth-stencil formats, or `ImageWrapper` has no buffer
+  /// layout for it, e.g. BGRA or two channel formats.
+  UnsupportedFormat(TextureFormat),
+  /// The render target was requested with zero viewports.
+  NoViews,
+  /// Sample count per exported pixel other than 1, 4 or 16.
+  UnsupportedSampleCount(u32),
+}
+
+
+impl fmt::Display for ExportError
+{
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result
+  {
+    match self
+    {
+      ExportError::SourceImageMissing => write!(f, "source image is not available on the GPU"),
+      ExportError::UnsupportedFormat(format) => write!(f, "texture format {format:?} can't be exported"),
+      ExportError::NoViews => write!(f, "render target needs at least one viewport"),
+      ExportError::UnsupportedSampleCount(samples) => write!(f, "unsupported sample count {samples}, expected 1, 4 or 16"),
+    }
+  }
+}
+
+
+impl std::error::Error for ExportError {}
+
+
+/// Unpadded bytes per row for a texture `width` pixels wide in `format`.
+pub(crate) fn bytes_per_row(format: TextureFormat, width: u32) -> Result<u32, ExportError>
+{
+  let (block_width, block_height) = format.block_dimensions();
+  if block_width != 1 || block_height != 1
+  {
+    return Err(ExportError::UnsupportedFormat(format));
+  }
+
+  format.block_copy_size(None)
+    .map(|block_size| width * block_size)
+    .ok_or(ExportError::UnsupportedFormat(format))
+}
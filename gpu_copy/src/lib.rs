@@ -1,11 +1,19 @@
 // Derived from https://github.com/paulkre/bevy_image_export
+mod error;
 mod node;
 mod plugin;
+mod saver;
 mod utils;
 
 pub use plugin::{
     GpuImageExport, GpuToCpuCpyPlugin, ImageExportBundle,
-    ImageExportSettings, ImageSource, ImageExportSystems, ExportImage, ExportedImages
+    ImageExportSettings, ImageSource, ImageExportSystems, ExportImage, ExportedImages,
+    ExportedFrame, ExportFrameCounter, FrameExported, RunSteps
 };
 
-pub use utils::{setup_render_target, SceneInfo};
+pub use error::ExportError;
+pub use saver::{FramePathTemplate, ImageSaver, DEFAULT_FRAME_PATH_TEMPLATE};
+pub use utils::{
+    AtlasLayout, remove_render_target, save_image, setup_render_target, setup_render_target_with_config, ImageWrapper,
+    RenderTargetConfig, SceneInfo, DEFAULT_JPEG_QUALITY
+};
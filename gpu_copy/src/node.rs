@@ -1,4 +1,4 @@
-use crate::ImageSource;
+use crate::{plugin::ExportStagingBuffer, ImageSource};
 
 use bevy::{
     ecs::world::World,
@@ -24,16 +24,29 @@ impl Node for ImageExportNode
     world: &World,
   ) -> Result<(), NodeRunError>
   {
-    for (_, source) in world.resource::<RenderAssets<ImageSource>>().iter()
+    let staging = world.get_resource::<ExportStagingBuffer>();
+
+    for (id, source) in world.resource::<RenderAssets<ImageSource>>().iter()
     {
+      let (buffer, offset) = match (staging, &source.buffer)
+      {
+        (Some(staging), _) => match staging.slot(&id)
+        {
+          Some((buffer, offset, _)) => (buffer, offset),
+          None => continue,
+        },
+        (None, Some(buffer)) => (buffer, 0),
+        (None, None) => continue,
+      };
+
       if let Some(gpu_image) = world.resource::<RenderAssets<Image>>().get(&source.source_handle)
       {
         render_context.command_encoder().copy_texture_to_buffer(
           gpu_image.texture.as_image_copy(),
           ImageCopyBuffer {
-            buffer: &source.buffer,
+            buffer,
             layout: ImageDataLayout {
-              offset: 0,
+              offset,
               bytes_per_row: Some(source.padded_bytes_per_row),
               rows_per_image: None,
             },
@@ -1,18 +1,21 @@
-use std::sync::Arc;
+use std::{collections::VecDeque, sync::{atomic::{AtomicU64, Ordering}, Arc}};
 
-use crate::{node::{ImageExportNode, NodeName}, utils::ImageWrapper};
+use crate::{error::{bytes_per_row, ExportError}, node::{ImageExportNode, NodeName}, saver::ImageSaver, utils::{despawn_export, ImageWrapper}};
 use bevy::{
-    app::{App, Plugin, PostUpdate},
-    asset::{Asset, AssetApp, Handle},
+    app::{App, AppExit, Last, Plugin, PostUpdate, PreUpdate},
+    asset::{Asset, AssetApp, AssetId, Assets, Handle},
     ecs::{
         bundle::Bundle,
         component::Component,
         entity::Entity,
+        event::{Event, EventWriter},
         query::{QueryItem, With, Without},
-        schedule::{apply_deferred, IntoSystemConfigs, IntoSystemSetConfigs, SystemSet},
+        removal_detection::RemovedComponents,
+        schedule::{apply_deferred, common_conditions::resource_exists, IntoSystemConfigs, IntoSystemSetConfigs, SystemSet},
         system::{
             lifetimeless::SRes, Commands, Local, Query, Res, ResMut, Resource, SystemParamItem,
         },
+        world::World,
     },
     reflect::Reflect,
     render::{
@@ -21,15 +24,22 @@ use bevy::{
         graph::CameraDriverLabel,
         render_asset::{PrepareAssetError, RenderAsset, RenderAssetPlugin, RenderAssets, RenderAssetUsages},
         render_graph::RenderGraph,
-        render_resource::{Buffer, BufferDescriptor, BufferUsages, Extent3d, MapMode},
+        render_resource::{
+            Buffer, BufferDescriptor, BufferSlice, BufferUsages, Extent3d, MapMode,
+            TextureFormat
+        },
         renderer::RenderDevice,
         texture::Image, Render, RenderApp, RenderSet
-    }, utils::HashMap,
+    }, utils::{HashMap, HashSet},
 };
+use crossbeam_channel::{Receiver, Sender};
 use futures::channel::oneshot;
+use image::DynamicImage;
 
 use parking_lot::{Mutex, RwLock};
-use wgpu::Maintain;
+#[cfg(feature = "parallel-unpad")]
+use rayon::prelude::*;
+use wgpu::{BufferAsyncError, Maintain};
 use ImageExportSystems::{SetupImageExport, SetupImageExportFlush};
 
 
@@ -37,15 +47,89 @@ use ImageExportSystems::{SetupImageExport, SetupImageExportFlush};
 pub struct ImageSource(pub Handle<Image>);
 
 
+#[derive(Default, Debug)]
+struct ExportBuffers
+{
+  published: RwLock<Arc<ImageWrapper>>,
+  back: Mutex<Arc<ImageWrapper>>,
+  /// Previously published frames, oldest first, when more than the latest
+  /// one is kept.
+  history: Mutex<VecDeque<Arc<ImageWrapper>>>,
+  history_len: usize,
+}
+
+
+/// Double buffered export image. The render app writes into the back buffer
+/// and swaps it with the published one once the frame is complete, so readers
+/// never observe a partially copied frame.
 #[derive(Clone, Default, Debug)]
-pub struct ExportImage(pub Arc<RwLock<ImageWrapper>>);
+pub struct ExportImage(Arc<ExportBuffers>);
 
 
 impl ExportImage
 {
-  pub fn new(size: Extent3d) -> Self
+  pub fn new(size: Extent3d, format: TextureFormat) -> Self
+  {
+    Self::with_history(size, format, 1)
+  }
+
+  /// Export image remembering its last `history_len` frames.
+  pub fn with_history(size: Extent3d, format: TextureFormat, history_len: usize) -> Self
+  {
+    let history_len = history_len.max(1);
+    Self(Arc::new(ExportBuffers
+    {
+      published: RwLock::new(Arc::new(ImageWrapper::new(size, format))),
+      back: Mutex::new(Arc::new(ImageWrapper::new(size, format))),
+      history: Mutex::new(VecDeque::with_capacity(history_len - 1)),
+      history_len,
+    }))
+  }
+
+  /// Most recently completed frame. Cloning the `Arc` is cheap and does not
+  /// hold any lock.
+  pub fn latest(&self) -> Arc<ImageWrapper>
+  {
+    self.0.published.read().clone()
+  }
+
+  /// Fills the back buffer with `update` and publishes it. Nothing is
+  /// published when `update` returns false, so a dropped frame leaves the
+  /// latest one in place. Returns whether the frame was published.
+  pub(crate) fn publish(&self, update: impl FnOnce(&mut ImageWrapper) -> bool) -> bool
+  {
+    let mut back = self.0.back.lock();
+    // If a reader still holds the old back frame it gets copied here instead
+    // of being overwritten under it.
+    if !update(Arc::make_mut(&mut back))
+    {
+      return false;
+    }
+    std::mem::swap(&mut *self.0.published.write(), &mut *back);
+
+    if self.0.history_len > 1 && back.frame_id > 0
+    {
+      let mut history = self.0.history.lock();
+      if history.len() + 1 >= self.0.history_len
+      {
+        history.pop_front();
+      }
+      // Shares the frame with the back buffer, which makes the next publish
+      // copy it rather than overwrite it.
+      history.push_back(back.clone());
+    }
+
+    true
+  }
+
+  /// Up to the configured number of most recent frames with their frame ids,
+  /// oldest first and ending with `latest`. Only the latest one is kept by
+  /// default.
+  pub fn history(&self) -> Vec<Arc<ImageWrapper>>
   {
-    Self(Arc::new(RwLock::new(ImageWrapper::new(size))))
+    let mut frames: Vec<_> = self.0.history.lock().iter().cloned().collect();
+    frames.push(self.latest());
+    frames
   }
 }
 
@@ -54,6 +138,123 @@ impl ExportImage
 pub struct ExportedImages(pub Arc<Mutex<HashMap<String, ExportImage>>>);
 
 
+impl ExportedImages
+{
+  /// Names of all exported targets.
+  pub fn names(&self) -> Vec<String>
+  {
+    self.0.lock().keys().cloned().collect()
+  }
+
+  /// Most recently completed frame of `name`, shared with the exporter.
+  pub fn frame(&self, name: &str) -> Option<Arc<ImageWrapper>>
+  {
+    self.0.lock().get(name).map(ExportImage::latest)
+  }
+
+  /// Frames `name` keeps in its history, oldest first, see
+  /// `ExportImage::history`.
+  pub fn history(&self, name: &str) -> Option<Vec<Arc<ImageWrapper>>>
+  {
+    self.0.lock().get(name).map(ExportImage::history)
+  }
+
+  /// Copy of the most recently completed frame of `name` with its frame id.
+  pub fn latest(&self, name: &str) -> Option<(DynamicImage, u64)>
+  {
+    self.frame(name).map(|image| (image.img_buffer.clone(), image.frame_id))
+  }
+
+  /// Latest frame of `name` if its frame id is newer than `last_seen`, which
+  /// is then advanced to it. Starting from 0 skips the blank frame a target
+  /// holds before anything has been rendered into it.
+  pub fn frame_if_newer(&self, name: &str, last_seen: &mut u64) -> Option<Arc<ImageWrapper>>
+  {
+    let image = self.frame(name)?;
+    if image.frame_id <= *last_seen
+    {
+      return None;
+    }
+
+    *last_seen = image.frame_id;
+    Some(image)
+  }
+
+  /// Owned copy of `frame_if_newer`.
+  pub fn take_if_newer(&self, name: &str, last_seen: &mut u64) -> Option<DynamicImage>
+  {
+    self.frame_if_newer(name, last_seen).map(|image| image.img_buffer.clone())
+  }
+
+  /// Calls `f` with the latest frame of every target. The map is only locked
+  /// while collecting the frames, not while `f` runs.
+  pub fn for_each(&self, mut f: impl FnMut(&str, &ImageWrapper))
+  {
+    let frames: Vec<_> = self.0.lock()
+      .iter()
+      .map(|(name, image)| (name.clone(), image.latest()))
+      .collect();
+
+    for (name, image) in &frames
+    {
+      f(name, image);
+    }
+  }
+}
+
+
+/// Sent in the main app whenever an export target receives a new frame.
+#[derive(Event, Clone, Debug)]
+pub struct FrameExported
+{
+  pub name: String,
+  /// See `ImageWrapper::frame_id`.
+  pub frame_id: u64,
+  /// See `ImageWrapper::render_frame`.
+  pub render_frame: u64,
+}
+
+
+#[derive(Resource)]
+struct FrameExportedSender(Sender<FrameExported>);
+
+
+/// Completed frame pushed to the channel installed with
+/// `GpuToCpuCpyPlugin::with_frame_channel`.
+#[derive(Clone, Debug)]
+pub struct ExportedFrame
+{
+  pub name: String,
+  pub image: Arc<ImageWrapper>,
+}
+
+
+#[derive(Resource)]
+struct FrameChannel(Sender<ExportedFrame>);
+
+
+#[derive(Resource)]
+struct FrameExportedReceiver(Receiver<FrameExported>);
+
+
+/// Number of render frames seen by the export system. Incremented in the
+/// render app and mirrored into the main app every `PreUpdate`, wrapping on
+/// overflow.
+#[derive(Resource, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct ExportFrameCounter(pub u64);
+
+
+/// Exit the app once `ExportFrameCounter` reaches this many frames, after
+/// every image queued on the `ImageSaver` has been written. Without it the app
+/// runs until it is closed.
+#[derive(Resource, Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RunSteps(pub u64);
+
+
+#[derive(Resource, Clone, Default)]
+struct SharedFrameCounter(Arc<AtomicU64>);
+
+
 impl From<Handle<Image>> for ImageSource
 {
   fn from(value: Handle<Image>) -> Self
@@ -67,6 +268,18 @@ impl From<Handle<Image>> for ImageSource
 pub struct ImageExportSettings
 {
   pub name: String,
+  /// JPEG quality (0-100) used when saving the target, `None` falls back to
+  /// `DEFAULT_JPEG_QUALITY`. Values above 100 are clamped.
+  pub quality: Option<u8>,
+  /// Copy the target back to the CPU only every Nth render frame. 1 copies
+  /// every frame.
+  pub export_interval: u32,
+  /// Flip the rows of the exported image vertically.
+  pub flip_y: bool,
+  /// The source is rendered this many times wider and higher than the
+  /// exported image and averaged down to it. Set by `setup_render_target`
+  /// from `RenderTargetConfig::samples`.
+  pub supersampling: u32,
 }
 
 
@@ -74,7 +287,30 @@ impl ImageExportSettings
 {
   pub fn new(name: String) -> Self
   {
-    Self { name }
+    Self { name, quality: None, export_interval: 1, flip_y: false, supersampling: 1 }
+  }
+
+  pub fn with_quality(mut self, quality: u8) -> Self
+  {
+    self.quality = Some(quality);
+    self
+  }
+
+  pub fn with_export_interval(mut self, export_interval: u32) -> Self
+  {
+    self.export_interval = export_interval.max(1);
+    self
+  }
+
+  pub fn with_flip_y(mut self, flip_y: bool) -> Self
+  {
+    self.flip_y = flip_y;
+    self
+  }
+
+  fn is_export_frame(&self, render_frame: u64) -> bool
+  {
+    render_frame % self.export_interval.max(1) as u64 == 0
   }
 }
 
@@ -82,7 +318,9 @@ impl ImageExportSettings
 #[derive(Clone)]
 pub struct GpuImageExport
 {
-  pub buffer: Buffer,
+  /// Readback buffer of this source, `None` when the plugin copies all sources
+  /// into the shared `ExportStagingBuffer` or the source can't be exported.
+  pub buffer: Option<Buffer>,
   pub source_handle: Handle<Image>,
   pub source_size: Extent3d,
   pub bytes_per_row: u32,
@@ -91,41 +329,102 @@ pub struct GpuImageExport
 
 
 impl GpuImageExport {
+  /// Placeholder for a source whose format can't be exported. It is never
+  /// copied, but being prepared keeps it from being retried every frame.
+  fn unsupported(source_handle: Handle<Image>, source_size: Extent3d) -> Self
+  {
+    Self
+    {
+      buffer: None,
+      source_handle,
+      source_size,
+      bytes_per_row: 0,
+      padded_bytes_per_row: 0,
+    }
+  }
+
+  fn is_exportable(&self) -> bool
+  {
+    self.bytes_per_row > 0
+  }
+
   fn get_bps(&self) -> (usize, usize, Extent3d)
   {
     (self.bytes_per_row as usize, self.padded_bytes_per_row as usize, self.source_size)
   }
+
+  fn buffer_size(&self) -> u64
+  {
+    self.source_size.height as u64 * self.padded_bytes_per_row as u64
+  }
+}
+
+
+/// Single readback buffer shared by all sources, each one copied at its own
+/// offset. Only present when the plugin is built with `with_batched_buffer`.
+#[derive(Resource, Default)]
+pub struct ExportStagingBuffer
+{
+  buffer: Option<Buffer>,
+  /// Offset and size of every source inside `buffer`.
+  layout: HashMap<AssetId<ImageSource>, (u64, u64)>,
+}
+
+
+impl ExportStagingBuffer
+{
+  pub(crate) fn slot(&self, id: &AssetId<ImageSource>) -> Option<(&Buffer, u64, u64)>
+  {
+    let buffer = self.buffer.as_ref()?;
+    let &(offset, size) = self.layout.get(id)?;
+    Some((buffer, offset, size))
+  }
 }
 
 
 impl RenderAsset for ImageSource
 {
-  type Param = (SRes<RenderDevice>, SRes<RenderAssets<Image>>);
+  type Param = (SRes<RenderDevice>, SRes<RenderAssets<Image>>, Option<SRes<ExportStagingBuffer>>);
   type PreparedAsset = GpuImageExport;
 
   fn prepare_asset(
     self: Self,
-    (device, images): &mut SystemParamItem<Self::Param>,
+    (device, images, staging): &mut SystemParamItem<Self::Param>,
   ) -> Result<Self::PreparedAsset, PrepareAssetError<Self>>
   {
-    let gpu_image = images.get(&self.0).unwrap();
+    let Some(gpu_image) = images.get(&self.0) else
+    {
+      log::debug!("Delaying export of {:?} | {}", self.0, ExportError::SourceImageMissing);
+      return Err(PrepareAssetError::RetryNextUpdate(self));
+    };
 
     let size = gpu_image.texture.size();
-    let format = &gpu_image.texture_format;
-    let bytes_per_row = (size.width / format.block_dimensions().0) * format.block_copy_size(None).unwrap();
+    let format = gpu_image.texture_format;
+    let bytes_per_row = match bytes_per_row(format, size.width)
+    {
+      Ok(bytes_per_row) if ImageWrapper::supports_format(format) => bytes_per_row,
+      _ =>
+      {
+        // A format doesn't change by waiting, so this is reported only once.
+        log::error!("Can't export image {:?} | {}", self.0, ExportError::UnsupportedFormat(format));
+        return Ok(GpuImageExport::unsupported(self.0, size));
+      },
+    };
 
     let padded_bytes_per_row = RenderDevice::align_copy_bytes_per_row(bytes_per_row as usize) as u32;
 
     let source_size = gpu_image.texture.size();
 
+    let buffer = staging.is_none().then(|| device.create_buffer(&BufferDescriptor {
+      label: Some("Image Export Buffer"),
+      size: (source_size.height * padded_bytes_per_row) as u64,
+      usage: BufferUsages::COPY_DST | BufferUsages::MAP_READ,
+      mapped_at_creation: false,
+    }));
+
     Ok(GpuImageExport
       {
-        buffer: device.create_buffer(&BufferDescriptor {
-          label: Some("Image Export Buffer"),
-          size: (source_size.height * padded_bytes_per_row) as u64,
-          usage: BufferUsages::COPY_DST | BufferUsages::MAP_READ,
-          mapped_at_creation: false,
-        }),
+        buffer,
         source_handle: self.0.clone(),
         source_size,
         bytes_per_row,
@@ -147,7 +446,7 @@ impl Default for ImageExportSettings
 {
   fn default() -> Self
   {
-    Self { name: "default_export".into() }
+    Self::new("default_export".into())
   }
 }
 
@@ -189,20 +488,184 @@ pub struct ImageExportBundle
 }
 
 
+/// Lays out every exportable source in the shared staging buffer, recreating
+/// the buffer whenever the set of sources changes.
+fn prepare_export_staging_buffer(
+  sources: Res<RenderAssets<ImageSource>>,
+  render_device: Res<RenderDevice>,
+  mut staging: ResMut<ExportStagingBuffer>,
+)
+{
+  let exportable = || sources.iter().filter(|(_, source)| source.is_exportable());
+  let layout_is_current = exportable().count() == staging.layout.len()
+    && exportable().all(|(id, source)|
+      staging.layout.get(&id).map_or(false, |&(_, size)| size == source.buffer_size()));
+
+  if layout_is_current
+  {
+    return;
+  }
+
+  let mut layout = HashMap::new();
+  let mut total_size = 0;
+  for (id, source) in exportable()
+  {
+    // Source sizes are multiples of the padded row size, so every offset
+    // stays aligned for copies and mapping.
+    layout.insert(id, (total_size, source.buffer_size()));
+    total_size += source.buffer_size();
+  }
+
+  log::debug!("Export staging buffer holds {} sources in {} bytes", layout.len(), total_size);
+
+  staging.buffer = (total_size > 0).then(|| render_device.create_buffer(&BufferDescriptor {
+    label: Some("Image Export Staging Buffer"),
+    size: total_size,
+    usage: BufferUsages::COPY_DST | BufferUsages::MAP_READ,
+    mapped_at_creation: false,
+  }));
+  staging.layout = layout;
+}
+
+
+fn map_slice(render_device: &RenderDevice, slice: &BufferSlice) -> oneshot::Receiver<Result<(), BufferAsyncError>>
+{
+  let (mapping_tx, mapping_rx) = oneshot::channel();
+
+  render_device.map_buffer(slice, MapMode::Read, move |res|
+  {
+    mapping_tx.send(res).unwrap();
+  });
+
+  mapping_rx
+}
+
+
+/// Rows handed to a rayon task at once when unpadding in parallel, fewer
+/// aren't worth the scheduling.
+#[cfg(feature = "parallel-unpad")]
+const MIN_ROWS_PER_TASK: usize = 16;
+
+
+/// Returns the pixel rows of a mapped source without the row padding. Tightly
+/// packed sources are returned as is, otherwise the rows are copied into
+/// `scratch`, which is reused across targets and frames to avoid allocating a
+/// new buffer for every copy. With the `parallel-unpad` feature the rows are
+/// copied concurrently, each straight to its offset in `scratch`, giving the
+/// same bytes as the serial copy.
+fn unpad_rows<'a>(
+  mapped_bytes: &'a [u8],
+  gpu_source: &GpuImageExport,
+  flip_y: bool,
+  scratch: &'a mut Vec<u8>,
+) -> &'a [u8]
+{
+  let (bytes_per_row, padded_bytes_per_row, source_size) = gpu_source.get_bps();
+
+  if bytes_per_row == padded_bytes_per_row && !flip_y
+  {
+    return mapped_bytes;
+  }
+
+  copy_unpadded_rows(mapped_bytes, scratch, bytes_per_row, padded_bytes_per_row, source_size.height as usize, flip_y);
+  scratch
+}
+
+
+/// Copies the rows with `copy_unpadded_rows_parallel` when the
+/// `parallel-unpad` feature is enabled, with `copy_unpadded_rows_serial`
+/// otherwise.
+fn copy_unpadded_rows(
+  mapped_bytes: &[u8],
+  scratch: &mut Vec<u8>,
+  bytes_per_row: usize,
+  padded_bytes_per_row: usize,
+  height: usize,
+  flip_y: bool,
+)
+{
+  #[cfg(feature = "parallel-unpad")]
+  copy_unpadded_rows_parallel(mapped_bytes, scratch, bytes_per_row, padded_bytes_per_row, height, flip_y);
+  #[cfg(not(feature = "parallel-unpad"))]
+  copy_unpadded_rows_serial(mapped_bytes, scratch, bytes_per_row, padded_bytes_per_row, height, flip_y);
+}
+
+
+#[cfg_attr(feature = "parallel-unpad", allow(dead_code))]
+fn copy_unpadded_rows_serial(
+  mapped_bytes: &[u8],
+  scratch: &mut Vec<u8>,
+  bytes_per_row: usize,
+  padded_bytes_per_row: usize,
+  height: usize,
+  flip_y: bool,
+)
+{
+  scratch.clear();
+  scratch.reserve(height * bytes_per_row);
+
+  let rows = mapped_bytes
+    .chunks(padded_bytes_per_row)
+    .map(|padded_row| &padded_row[..bytes_per_row]);
+
+  if flip_y
+  {
+    rows.rev().for_each(|row| scratch.extend_from_slice(row));
+  }
+  else
+  {
+    rows.for_each(|row| scratch.extend_from_slice(row));
+  }
+}
+
+
+#[cfg(feature = "parallel-unpad")]
+fn copy_unpadded_rows_parallel(
+  mapped_bytes: &[u8],
+  scratch: &mut Vec<u8>,
+  bytes_per_row: usize,
+  padded_bytes_per_row: usize,
+  height: usize,
+  flip_y: bool,
+)
+{
+  scratch.clear();
+  scratch.resize(height * bytes_per_row, 0);
+
+  scratch
+    .par_chunks_mut(bytes_per_row)
+    .with_min_len(MIN_ROWS_PER_TASK)
+    .enumerate()
+    .for_each(|(row, unpadded_row)|
+    {
+      let source_row = if flip_y { height - 1 - row } else { row };
+      let start = source_row * padded_bytes_per_row;
+      unpadded_row.copy_from_slice(&mapped_bytes[start..start + bytes_per_row]);
+    });
+}
+
+
+#[allow(clippy::too_many_arguments)]
 fn save_buffer_as_resource(
   export_bundles: Query<(
       &Handle<ImageSource>,
       &ImageExportSettings,
   )>,
   sources: Res<RenderAssets<ImageSource>>,
+  staging: Option<Res<ExportStagingBuffer>>,
   render_device: Res<RenderDevice>,
   exported_images: ResMut<ExportedImages>,
-  mut frame_id: Local<u64>,
+  frame_sender: Res<FrameExportedSender>,
+  frame_channel: Option<Res<FrameChannel>>,
+  frame_counter: Res<SharedFrameCounter>,
+  mut target_frame_ids: Local<HashMap<String, u64>>,
+  mut image_bytes: Local<Vec<u8>>,
 )
 {
-  *frame_id = frame_id.wrapping_add(1);
+  // fetch_add wraps around on overflow.
+  let render_frame = frame_counter.0.fetch_add(1, Ordering::Relaxed).wrapping_add(1);
 
-  let mut locked_images = exported_images.0.lock();
+  let locked_images = exported_images.0.lock();
 
   if locked_images.is_empty()
   {
@@ -211,61 +674,268 @@ fn save_buffer_as_resource(
 
   log::debug!("num of export bundles {}", export_bundles.iter().len());
 
-  let mut futures = Vec::new();
+  let due_sources: Vec<_> = export_bundles
+    .iter()
+    .filter(|(_, settings)| settings.is_export_frame(render_frame))
+    .filter_map(|(source_handle, settings)| match sources.get(source_handle)
+    {
+      Some(gpu_source) => Some((source_handle.id(), gpu_source, settings)),
+      None =>
+      {
+        log::debug!("Skipping export of {}, its GPU source isn't prepared yet", settings.name);
+        None
+      },
+    })
+    .collect();
+
+  if due_sources.is_empty()
+  {
+    return;
+  }
 
-  for (source_handle, _) in &export_bundles
+  let mut publish = |settings: &ImageExportSettings, size: Extent3d, image_bytes: &[u8]|
   {
-    if let Some(gpu_source) = sources.get(source_handle)
+    let Some(export_img) = locked_images.get(&settings.name) else
+    {
+      return;
+    };
+
+    // Frame ids only advance when a frame is actually published, so the
+    // first exported frame of every target is 1.
+    let frame_id = target_frame_ids.get(&settings.name).map_or(1, |frame_id| frame_id.wrapping_add(1));
+    let published = export_img.publish(|buffer|
     {
-      let slice = gpu_source.buffer.slice(..);
+      let updated = buffer.update_data_supersampled(frame_id, size, image_bytes, settings.supersampling);
+      if updated
+      {
+        buffer.render_frame = render_frame;
+      }
+      updated
+    });
+    if !published
+    {
+      return;
+    }
+    target_frame_ids.insert(settings.name.clone(), frame_id);
+
+    // The main app may not be listening, in which case the event is dropped.
+    let _ = frame_sender.0.send(FrameExported { name: settings.name.clone(), frame_id, render_frame });
 
-      let (mapping_tx, mapping_rx) = oneshot::channel();
+    if let Some(frame_channel) = &frame_channel
+    {
+      // Full channels and dropped receivers simply miss the frame.
+      let _ = frame_channel.0.try_send(ExportedFrame { name: settings.name.clone(), image: export_img.latest() });
+    }
+  };
+
+  if let Some(staging) = &staging
+  {
+    // All sources share one buffer, so it is mapped and unmapped once.
+    let Some(buffer) = &staging.buffer else
+    {
+      return;
+    };
 
-      render_device.map_buffer(&slice, MapMode::Read, move |res|
+    let slice = buffer.slice(..);
+    let future = map_slice(&render_device, &slice);
+    render_device.poll(Maintain::Wait);
+    futures_lite::future::block_on(future).unwrap().unwrap();
+    {
+      let mapped_bytes = slice.get_mapped_range();
+      for &(id, gpu_source, settings) in &due_sources
       {
-        mapping_tx.send(res).unwrap();
-      });
+        let Some((_, offset, size)) = staging.slot(&id) else
+        {
+          continue;
+        };
 
-      futures.push((slice, mapping_rx));
+        let source_bytes = &mapped_bytes[offset as usize..(offset + size) as usize];
+        publish(settings, gpu_source.source_size, unpad_rows(source_bytes, gpu_source, settings.flip_y, &mut image_bytes));
+      }
+    }
+    buffer.unmap();
+    return;
+  }
+
+  let mut futures = Vec::new();
+
+  for &(_, gpu_source, settings) in &due_sources
+  {
+    if let Some(buffer) = &gpu_source.buffer
+    {
+      let slice = buffer.slice(..);
+      let future = map_slice(&render_device, &slice);
+      futures.push((gpu_source, buffer, slice, future, settings));
     }
   }
 
   render_device.poll(Maintain::Wait);
-  for ((slice, future), (source_handle, settings)) in futures.iter_mut().zip(export_bundles.iter())
+  for (gpu_source, buffer, slice, future, settings) in futures
   {
     futures_lite::future::block_on(future).unwrap().unwrap();
-    let mut image_bytes = slice.get_mapped_range().to_vec();
-    if let Some(gpu_source) = sources.get(source_handle)
     {
-      gpu_source.buffer.unmap();
-      let (bytes_per_row, padded_bytes_per_row, source_size) = gpu_source.get_bps();
-
-      if bytes_per_row != padded_bytes_per_row
-      {
-        let mut unpadded_bytes =
-            Vec::<u8>::with_capacity(source_size.height as usize * bytes_per_row);
+      let mapped_bytes = slice.get_mapped_range();
+      publish(settings, gpu_source.source_size, unpad_rows(&mapped_bytes, gpu_source, settings.flip_y, &mut image_bytes));
+    }
+    buffer.unmap();
+  }
+}
 
-        for padded_row in image_bytes.chunks(padded_bytes_per_row)
-        {
-          unpadded_bytes.extend_from_slice(&padded_row[..bytes_per_row]);
-        }
 
-        image_bytes = unpadded_bytes;
-      }
+/// Forgets the `ExportedImages` of targets whose last export bundle is gone.
+/// Dropping the bundle drops its `ImageSource`, which releases the readback
+/// buffer on the render side and shrinks the shared staging buffer.
+fn forget_removed_exports(
+  mut removed_bundles: RemovedComponents<ImageExportSettings>,
+  export_bundles: Query<&ImageExportSettings>,
+  exported_images: Res<ExportedImages>,
+)
+{
+  if removed_bundles.read().count() == 0
+  {
+    return;
+  }
 
-      if let Some(export_img) = locked_images.get_mut(&settings.name)
-      {
-        let mut buffer = export_img.0.write();
-        buffer.update_data(*frame_id, &image_bytes);
-      }
+  let live_targets: HashSet<&str> = export_bundles.iter().map(|settings| settings.name.as_str()).collect();
+  exported_images.0.lock().retain(|name, _|
+  {
+    let is_live = live_targets.contains(name.as_str());
+    if !is_live
+    {
+      log::debug!("Export target {name} has no bundle left, forgetting it");
     }
+    is_live
+  });
+}
+
+
+fn emit_frame_exported_events(
+  frame_receiver: Res<FrameExportedReceiver>,
+  mut frame_exported: EventWriter<FrameExported>,
+)
+{
+  frame_exported.send_batch(frame_receiver.0.try_iter());
+}
+
+
+fn sync_export_frame_counter(
+  shared_counter: Res<SharedFrameCounter>,
+  mut frame_counter: ResMut<ExportFrameCounter>,
+)
+{
+  frame_counter.0 = shared_counter.0.load(Ordering::Relaxed);
+}
+
+
+fn exit_after_run_steps(
+  run_steps: Res<RunSteps>,
+  frame_counter: Res<ExportFrameCounter>,
+  image_saver: Option<Res<ImageSaver>>,
+  mut app_exit: EventWriter<AppExit>,
+  mut exiting: Local<bool>,
+)
+{
+  if *exiting || frame_counter.0 < run_steps.0
+  {
+    return;
   }
+
+  log::info!("Ran {} frames, exiting", frame_counter.0);
+  if let Some(image_saver) = image_saver
+  {
+    image_saver.flush();
+  }
+
+  app_exit.send(AppExit);
+  *exiting = true;
 }
 
 
 /// Plugin enabling the generation of image sequences.
 #[derive(Default)]
-pub struct GpuToCpuCpyPlugin;
+pub struct GpuToCpuCpyPlugin
+{
+  frame_sender: Option<Sender<ExportedFrame>>,
+  batched_buffer: bool,
+}
+
+
+impl GpuToCpuCpyPlugin
+{
+  /// Also push every completed frame into `sender`, so code outside of Bevy
+  /// can receive frames without locking `ExportedImages`.
+  pub fn with_frame_channel(mut self, sender: Sender<ExportedFrame>) -> Self
+  {
+    self.frame_sender = Some(sender);
+    self
+  }
+
+  /// Copy all sources into one shared buffer, so a single map and unmap is
+  /// done per frame instead of one per source. Pays off with many targets.
+  pub fn with_batched_buffer(mut self) -> Self
+  {
+    self.batched_buffer = true;
+    self
+  }
+
+  /// Stops all exports of `app` and frees their GPU resources right away:
+  /// despawns every export bundle, drops all `ImageSource`s with their
+  /// readback and staging buffers and takes the copy node out of the render
+  /// graph. Meant for apps embedding the simulation that restart it in the
+  /// same process. The app keeps running, it just exports nothing anymore.
+  pub fn teardown(app: &mut App)
+  {
+    let world = &mut app.world;
+    let export_entities: Vec<Entity> = world.query_filtered::<Entity, With<ImageExportSettings>>()
+      .iter(world)
+      .collect();
+    for entity in export_entities
+    {
+      despawn_export(world, entity);
+    }
+
+    if let Some(exported_images) = world.get_resource::<ExportedImages>()
+    {
+      exported_images.0.lock().clear();
+    }
+
+    if let Some(mut sources) = world.get_resource_mut::<Assets<ImageSource>>()
+    {
+      let ids: Vec<_> = sources.ids().collect();
+      for id in ids
+      {
+        sources.remove(id);
+      }
+    }
+
+    let Ok(render_app) = app.get_sub_app_mut(RenderApp) else
+    {
+      return;
+    };
+    let world = &mut render_app.world;
+
+    // Don't wait for the removals to be extracted, the app may be dropped
+    // before the next frame.
+    if let Some(mut gpu_sources) = world.get_resource_mut::<RenderAssets<ImageSource>>()
+    {
+      let ids: Vec<_> = gpu_sources.iter().map(|(id, _)| id).collect();
+      for id in ids
+      {
+        gpu_sources.remove(id);
+      }
+    }
+
+    if let Some(mut staging) = world.get_resource_mut::<ExportStagingBuffer>()
+    {
+      *staging = ExportStagingBuffer::default();
+    }
+
+    if let Err(e) = world.resource_mut::<RenderGraph>().remove_node(NodeName)
+    {
+      log::warn!("Image export node was already removed | {e:?}");
+    }
+  }
+}
 
 
 #[derive(Debug, Hash, PartialEq, Eq, Clone, SystemSet)]
@@ -282,7 +952,17 @@ impl Plugin for GpuToCpuCpyPlugin
   {
     let exported_images = ExportedImages::default();
 
-    app.insert_resource(exported_images.clone());
+    let (frame_sender, frame_receiver) = crossbeam_channel::unbounded();
+    let frame_counter = SharedFrameCounter::default();
+
+    app.insert_resource(exported_images.clone())
+       .insert_resource(FrameExportedReceiver(frame_receiver))
+       .insert_resource(frame_counter.clone())
+       .init_resource::<ExportFrameCounter>()
+       .add_event::<FrameExported>()
+       .add_systems(PreUpdate, (emit_frame_exported_events, sync_export_frame_counter))
+       .add_systems(Last, exit_after_run_steps.run_if(resource_exists::<RunSteps>))
+       .add_systems(Last, forget_removed_exports);
 
     app.configure_sets(
         PostUpdate,
@@ -305,7 +985,21 @@ impl Plugin for GpuToCpuCpyPlugin
 
     let render_app = app.sub_app_mut(RenderApp);
 
-    render_app.insert_resource(exported_images);
+    render_app.insert_resource(exported_images)
+              .insert_resource(FrameExportedSender(frame_sender))
+              .insert_resource(frame_counter);
+
+    if let Some(sender) = &self.frame_sender
+    {
+      render_app.insert_resource(FrameChannel(sender.clone()));
+    }
+
+    if self.batched_buffer
+    {
+      render_app
+        .init_resource::<ExportStagingBuffer>()
+        .add_systems(Render, prepare_export_staging_buffer.in_set(RenderSet::PrepareResources));
+    }
 
     render_app.add_systems(
       Render,
@@ -318,3 +1012,91 @@ impl Plugin for GpuToCpuCpyPlugin
     graph.add_node_edge(CameraDriverLabel, NodeName);
   }
 }
+
+
+#[cfg(test)]
+mod tests
+{
+  use super::*;
+
+  #[test]
+  fn dropped_frames_are_not_published()
+  {
+    let size = Extent3d { width: 2, height: 2, ..Default::default() };
+    let export_img = ExportImage::new(size, TextureFormat::Rgba8UnormSrgb);
+    let frame = [7u8; 16];
+
+    assert!(export_img.publish(|buffer| buffer.update_data(1, size, &frame)));
+    assert!(!export_img.publish(|buffer| buffer.update_data(2, size, &frame[..8])));
+
+    let latest = export_img.latest();
+    assert_eq!(latest.frame_id, 1);
+    assert_eq!(latest.img_buffer.as_bytes(), frame);
+  }
+
+  #[test]
+  fn supersampled_frames_are_averaged_down()
+  {
+    let size = Extent3d { width: 1, height: 1, ..Default::default() };
+    let rendered_size = Extent3d { width: 2, height: 2, ..Default::default() };
+    let export_img = ExportImage::new(size, TextureFormat::Rgba8Unorm);
+    let frame = [[200u8, 0, 0, 255], [200, 0, 0, 255], [0, 100, 0, 255], [0, 100, 0, 255]].concat();
+
+    assert!(export_img.publish(|buffer| buffer.update_data_supersampled(1, rendered_size, &frame, 2)));
+
+    let latest = export_img.latest();
+    assert_eq!((latest.img_buffer.width(), latest.img_buffer.height()), (1, 1));
+    assert_eq!(latest.img_buffer.as_bytes(), [100, 50, 0, 255]);
+  }
+
+  const WIDTH_BYTES: usize = 12;
+  const PADDED_WIDTH_BYTES: usize = 256;
+  const HEIGHT: usize = 50;
+
+  /// Padded buffer with the pixel bytes counting up row after row and the
+  /// padding filled with 0xff.
+  fn padded_rows() -> Vec<u8>
+  {
+    (0..HEIGHT)
+      .flat_map(|row| (0..PADDED_WIDTH_BYTES).map(move |column|
+        if column < WIDTH_BYTES { (row * WIDTH_BYTES + column) as u8 } else { 0xff }))
+      .collect()
+  }
+
+  fn unpad(copy: fn(&[u8], &mut Vec<u8>, usize, usize, usize, bool), flip_y: bool) -> Vec<u8>
+  {
+    // Leftovers of a previous, bigger frame must not leak into the result.
+    let mut scratch = vec![0xaa; 2 * HEIGHT * PADDED_WIDTH_BYTES];
+    copy(&padded_rows(), &mut scratch, WIDTH_BYTES, PADDED_WIDTH_BYTES, HEIGHT, flip_y);
+    scratch
+  }
+
+  #[test]
+  fn serial_unpadding_drops_the_padding()
+  {
+    let unpadded = unpad(copy_unpadded_rows_serial, false);
+    assert_eq!(unpadded, (0..HEIGHT * WIDTH_BYTES).map(|byte| byte as u8).collect::<Vec<_>>());
+
+    let flipped = unpad(copy_unpadded_rows_serial, true);
+    assert_eq!(flipped[..WIDTH_BYTES], unpadded[(HEIGHT - 1) * WIDTH_BYTES..]);
+  }
+
+  #[test]
+  fn selected_unpadding_matches_serial()
+  {
+    for flip_y in [false, true]
+    {
+      assert_eq!(unpad(copy_unpadded_rows, flip_y), unpad(copy_unpadded_rows_serial, flip_y));
+    }
+  }
+
+  #[cfg(feature = "parallel-unpad")]
+  #[test]
+  fn parallel_unpadding_matches_serial()
+  {
+    for flip_y in [false, true]
+    {
+      assert_eq!(unpad(copy_unpadded_rows_parallel, flip_y), unpad(copy_unpadded_rows_serial, flip_y));
+    }
+  }
+}
@@ -0,0 +1,214 @@
+use std::{fs::{self, OpenOptions}, io::{self, Write}, path::{Path, PathBuf}, sync::Arc, thread};
+
+use bevy::ecs::system::Resource;
+use crossbeam_channel::{bounded, Sender, TrySendError};
+
+use crate::ImageWrapper;
+
+
+const DEFAULT_QUEUE_SIZE: usize = 32;
+pub const DEFAULT_FRAME_PATH_TEMPLATE: &str = "{name}_{frame:06}.{ext}";
+
+
+struct SaveJob
+{
+  path: PathBuf,
+  image: Arc<ImageWrapper>,
+  quality: Option<u8>,
+}
+
+
+enum SaverMessage
+{
+  Save(SaveJob),
+  /// Appends a line of text to the file, e.g. a newline delimited JSON record.
+  Append { path: PathBuf, line: String },
+  /// Answered once every job queued before it has been written.
+  Flush(Sender<()>),
+}
+
+
+/// Writes images to disk on a single worker thread. Jobs are processed in the
+/// order they were queued; when the bounded queue is full new frames are
+/// dropped instead of blocking the caller.
+#[derive(Resource)]
+pub struct ImageSaver
+{
+  sender: Sender<SaverMessage>,
+}
+
+
+impl Default for ImageSaver
+{
+  fn default() -> Self
+  {
+    Self::new(DEFAULT_QUEUE_SIZE)
+  }
+}
+
+
+impl ImageSaver
+{
+  pub fn new(queue_size: usize) -> Self
+  {
+    let (sender, receiver) = bounded::<SaverMessage>(queue_size);
+
+    thread::Builder::new()
+      .name("image_saver".into())
+      .spawn(move ||
+      {
+        for message in receiver.iter()
+        {
+          match message
+          {
+            SaverMessage::Save(job) =>
+            {
+              if let Err(e) = job.image.save(&job.path, job.quality)
+              {
+                log::error!("Couldn't save image {:?} | {e:?}", job.path);
+              }
+            },
+            SaverMessage::Append { path, line } =>
+            {
+              let appended = OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(&path)
+                .and_then(|mut file| writeln!(file, "{line}"));
+              if let Err(e) = appended
+              {
+                log::error!("Couldn't append to {:?} | {e:?}", path);
+              }
+            },
+            SaverMessage::Flush(done) =>
+            {
+              let _ = done.send(());
+            },
+          }
+        }
+      })
+      .expect("Failed to spawn the image saver thread");
+
+    Self { sender }
+  }
+
+  /// Queues `image` to be written to `path`. Returns false if the frame was
+  /// dropped.
+  pub fn save<P: Into<PathBuf>>(&self, path: P, image: Arc<ImageWrapper>) -> bool
+  {
+    self.save_with_quality(path, image, None)
+  }
+
+  pub fn save_with_quality<P: Into<PathBuf>>(&self, path: P, image: Arc<ImageWrapper>, quality: Option<u8>) -> bool
+  {
+    let path = path.into();
+    let job = SaveJob { path: path.clone(), image, quality };
+    match self.sender.try_send(SaverMessage::Save(job))
+    {
+      Ok(()) => true,
+      Err(TrySendError::Full(_)) =>
+      {
+        log::warn!("Image save queue is full, dropping {:?}", path);
+        false
+      },
+      Err(TrySendError::Disconnected(_)) =>
+      {
+        log::error!("Image saver thread is gone, dropping {:?}", path);
+        false
+      },
+    }
+  }
+
+  /// Queues `line` to be appended to `path`, behind any queued images. Returns
+  /// false if the line was dropped.
+  pub fn append_line<P: Into<PathBuf>>(&self, path: P, line: String) -> bool
+  {
+    let path = path.into();
+    match self.sender.try_send(SaverMessage::Append { path: path.clone(), line })
+    {
+      Ok(()) => true,
+      Err(TrySendError::Full(_)) =>
+      {
+        log::warn!("Save queue is full, dropping a line for {:?}", path);
+        false
+      },
+      Err(TrySendError::Disconnected(_)) =>
+      {
+        log::error!("Image saver thread is gone, dropping a line for {:?}", path);
+        false
+      },
+    }
+  }
+
+  /// Blocks until everything queued so far has been written to disk.
+  pub fn flush(&self)
+  {
+    let (done_sender, done_receiver) = bounded(1);
+    if self.sender.send(SaverMessage::Flush(done_sender)).is_err()
+    {
+      log::error!("Image saver thread is gone, can't flush pending saves");
+      return;
+    }
+
+    let _ = done_receiver.recv();
+  }
+}
+
+
+/// Builds the paths of saved frames from a file name template inside a base
+/// directory. The template can refer to `{name}` (the export target name),
+/// `{frame}` (the frame id, `{frame:06}` zero pads it to 6 digits) and
+/// `{ext}`. Unknown placeholders are kept as they are.
+#[derive(Resource, Clone, Debug)]
+pub struct FramePathTemplate
+{
+  dir: PathBuf,
+  template: String,
+}
+
+
+impl FramePathTemplate
+{
+  /// Creates `dir` and its parents, once, so saving frames doesn't have to.
+  pub fn new<P: Into<PathBuf>, T: Into<String>>(dir: P, template: T) -> io::Result<Self>
+  {
+    let dir = dir.into();
+    fs::create_dir_all(&dir)?;
+    Ok(Self { dir, template: template.into() })
+  }
+
+  pub fn dir(&self) -> &Path
+  {
+    &self.dir
+  }
+
+  pub fn path(&self, name: &str, frame_id: u64, ext: &str) -> PathBuf
+  {
+    let mut file_name = String::with_capacity(self.template.len() + name.len());
+    let mut rest = self.template.as_str();
+
+    while let Some(start) = rest.find('{')
+    {
+      let Some(length) = rest[start..].find('}') else { break; };
+
+      file_name.push_str(&rest[..start]);
+      let placeholder = &rest[start + 1..start + length];
+      let (key, width) = placeholder.split_once(':').unwrap_or((placeholder, ""));
+      match key
+      {
+        "name" => file_name.push_str(name),
+        "ext" => file_name.push_str(ext),
+        "frame" =>
+        {
+          let width = width.parse().unwrap_or(0);
+          file_name.push_str(&format!("{frame_id:0width$}"));
+        },
+        _ => file_name.push_str(&rest[start..=start + length]),
+      }
+      rest = &rest[start + length + 1..];
+    }
+    file_name.push_str(rest);
+
+    self.dir.join(file_name)
+  }
+}
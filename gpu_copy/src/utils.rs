@@ -1,60 +1,313 @@
 use bevy::{
-    asset::Assets,
+    asset::{Assets, Handle},
+    core_pipeline::core_2d::Camera2dBundle,
     ecs::{
+        component::Component,
+        entity::Entity,
         event::Event,
-        system::{Commands, ResMut, Resource},
+        system::{Commands, Query, ResMut, Resource},
+        world::World,
     },
+    math::UVec2,
     render::{
-        camera::RenderTarget,
+        camera::{Camera, ClearColorConfig, RenderTarget, Viewport},
+        color::Color,
         render_resource::{
             Extent3d, TextureDescriptor, TextureDimension, TextureFormat, TextureUsages,
         },
         texture::Image,
+        view::RenderLayers,
     },
+    utils::default,
 };
-use std::{io::Cursor, ops::Deref};
+use wgpu::Features;
+use std::{fs::File, io::{BufWriter, Cursor}, ops::Deref, path::Path};
 
 use base64::{engine::general_purpose, Engine};
-use image::{EncodableLayout, ImageBuffer, ImageOutputFormat, Pixel, Rgba, RgbaImage};
+use image::{
+    codecs::jpeg::JpegEncoder, imageops::FilterType, ColorType, DynamicImage, EncodableLayout,
+    ImageBuffer, ImageOutputFormat, ImageResult, Pixel, RgbaImage,
+};
 
-use crate::{ImageExportBundle, ImageSource, ExportImage, ExportedImages, ImageExportSettings};
+use crate::{
+    error::{bytes_per_row, ExportError}, ImageExportBundle, ImageSource, ExportImage,
+    ExportedImages, ImageExportSettings
+};
 
 
-#[derive(Clone, Default, Debug)]
+#[derive(Clone, Debug)]
 pub struct ImageWrapper
 {
-  pub img_buffer: ImageBuffer<Rgba<u8>, Vec<u8>>,
+  /// Pixel data in the layout matching `format`. 8-bit targets are stored as
+  /// `ImageRgba8`, single channel `R8Unorm` targets as `ImageLuma8`,
+  /// `Rgba16Unorm` targets as `ImageRgba16` and float targets as
+  /// `ImageRgba32F`.
+  pub img_buffer: DynamicImage,
+  pub format: TextureFormat,
+  /// Frames published to this target so far, 1 for its first frame. Targets
+  /// exporting only every Nth render frame count just the exported ones.
   pub frame_id: u64,
+  /// `ExportFrameCounter` value of the render frame this frame was copied
+  /// in, 0 before the first frame.
+  pub render_frame: u64,
+}
+
+
+impl Default for ImageWrapper
+{
+  fn default() -> Self
+  {
+    Self
+    {
+      img_buffer: DynamicImage::new_rgba8(0, 0),
+      format: TextureFormat::Rgba8UnormSrgb,
+      frame_id: 0,
+      render_frame: 0,
+    }
+  }
 }
 
 
 impl ImageWrapper
 {
-  pub fn new(size: Extent3d) -> Self
+  /// Whether frames in `format` can be stored without converting them, see
+  /// `img_buffer` for the layouts.
+  pub fn supports_format(format: TextureFormat) -> bool
+  {
+    matches!(format,
+             TextureFormat::R8Unorm
+             | TextureFormat::Rgba8Unorm
+             | TextureFormat::Rgba8UnormSrgb
+             | TextureFormat::Rgba16Unorm
+             | TextureFormat::Rgba16Float
+             | TextureFormat::Rgba32Float)
+  }
+
+  pub fn new(size: Extent3d, format: TextureFormat) -> Self
   {
+    let img_buffer = match format
+    {
+      TextureFormat::R8Unorm => DynamicImage::new_luma8(size.width, size.height),
+      TextureFormat::Rgba16Unorm => DynamicImage::new_rgba16(size.width, size.height),
+      TextureFormat::Rgba16Float | TextureFormat::Rgba32Float =>
+        DynamicImage::new_rgba32f(size.width, size.height),
+      _ => DynamicImage::new_rgba8(size.width, size.height),
+    };
+
     Self
     {
-      img_buffer: ImageBuffer::new(size.width, size.height),
+      img_buffer,
+      format,
       frame_id: 0,
+      render_frame: 0,
     }
   }
 }
 
 
+/// Converts an IEEE 754 half precision float to `f32`.
+fn f16_to_f32(bits: u16) -> f32
+{
+  let sign = ((bits >> 15) as u32) << 31;
+  let exponent = ((bits >> 10) & 0x1f) as u32;
+  let mantissa = (bits & 0x3ff) as u32;
+
+  let value = match exponent
+  {
+    // Zero and subnormals
+    0 =>
+    {
+      let magnitude = mantissa as f32 * 2f32.powi(-24);
+      return if sign == 0 { magnitude } else { -magnitude };
+    },
+    // Infinity and NaN
+    0x1f => sign | 0x7f80_0000 | (mantissa << 13),
+    _ => sign | ((exponent + 112) << 23) | (mantissa << 13),
+  };
+
+  f32::from_bits(value)
+}
+
+
 impl ImageWrapper
 {
+  /// Copies a frame of `size` into the buffer. The buffer is reallocated when
+  /// the target was resized, and frames whose byte length still doesn't match
+  /// are dropped instead of panicking. Returns whether the frame was copied.
   pub fn update_data(
     &mut self,
     frame_id: u64,
-    image_bytes: &Vec<u8>,
-  )
+    size: Extent3d,
+    image_bytes: &[u8],
+  ) -> bool
   {
+    if (self.img_buffer.width(), self.img_buffer.height()) != (size.width, size.height)
+    {
+      log::warn!("Export buffer resized from {}x{} to {}x{}",
+                 self.img_buffer.width(), self.img_buffer.height(), size.width, size.height);
+      self.img_buffer = Self::new(size, self.format).img_buffer;
+    }
+
+    let expected_len = bytes_per_row(self.format, size.width)
+      .map_or(0, |row_bytes| row_bytes as usize * size.height as usize);
+    if image_bytes.len() != expected_len
+    {
+      log::warn!("Dropping frame {} with {} bytes, expected {} for {:?}",
+                 frame_id, image_bytes.len(), expected_len, self.format);
+      return false;
+    }
+
+    match (&mut self.img_buffer, self.format)
+    {
+      (DynamicImage::ImageRgba32F(buffer), TextureFormat::Rgba16Float) =>
+      {
+        for (dst, src) in buffer.iter_mut().zip(image_bytes.chunks_exact(2))
+        {
+          *dst = f16_to_f32(u16::from_ne_bytes([src[0], src[1]]));
+        }
+      },
+      (DynamicImage::ImageRgba32F(buffer), _) =>
+      {
+        for (dst, src) in buffer.iter_mut().zip(image_bytes.chunks_exact(4))
+        {
+          *dst = f32::from_ne_bytes([src[0], src[1], src[2], src[3]]);
+        }
+      },
+      (DynamicImage::ImageRgba16(buffer), _) =>
+      {
+        for (dst, src) in buffer.iter_mut().zip(image_bytes.chunks_exact(2))
+        {
+          *dst = u16::from_ne_bytes([src[0], src[1]]);
+        }
+      },
+      (DynamicImage::ImageRgba8(buffer), _) => buffer.copy_from_slice(image_bytes),
+      (DynamicImage::ImageLuma8(buffer), _) => buffer.copy_from_slice(image_bytes),
+      (buffer, format) =>
+      {
+        log::warn!("Unsupported export buffer {:?} for format {:?}", buffer.color(), format);
+        return false;
+      },
+    }
+
+    self.frame_id = frame_id;
+    true
+  }
+
+  /// Like `update_data`, but for a frame rendered `supersampling` times as
+  /// wide and high as this image, which is averaged down to it.
+  pub fn update_data_supersampled(
+    &mut self,
+    frame_id: u64,
+    size: Extent3d,
+    image_bytes: &[u8],
+    supersampling: u32,
+  ) -> bool
+  {
+    if supersampling <= 1
+    {
+      return self.update_data(frame_id, size, image_bytes);
+    }
+
+    let mut rendered = Self::new(size, self.format);
+    if !rendered.update_data(frame_id, size, image_bytes)
+    {
+      return false;
+    }
+
+    let (width, height) = (size.width / supersampling, size.height / supersampling);
+    self.img_buffer = rendered.img_buffer.resize_exact(width, height, FilterType::Triangle);
     self.frame_id = frame_id;
-    self.img_buffer.copy_from_slice(image_bytes);
+    true
+  }
+
+  /// Saves the image, using `quality` for the JPEG encoder when the path has a
+  /// `jpeg`/`jpg` extension. Other formats ignore the quality. Float targets
+  /// keep their full range when saved as `.exr`.
+  pub fn save<Q: AsRef<Path>>(&self, path: Q, quality: Option<u8>) -> ImageResult<()>
+  {
+    save_image(&self.img_buffer, path.as_ref(), quality)
+  }
+
+  /// Channel planar (NCHW with N = 1) copy of the image, the layout tensor
+  /// libraries expect. Luma targets give a single channel, all others RGBA.
+  /// With `normalize` integer formats are scaled into 0-1; float targets are
+  /// returned as they are either way.
+  pub fn to_nchw_f32(&self, normalize: bool) -> Vec<f32>
+  {
+    match &self.img_buffer
+    {
+      DynamicImage::ImageLuma8(buffer) =>
+        planar_f32(buffer, if normalize { 1.0 / u8::MAX as f32 } else { 1.0 }),
+      DynamicImage::ImageRgba8(buffer) =>
+        planar_f32(buffer, if normalize { 1.0 / u8::MAX as f32 } else { 1.0 }),
+      DynamicImage::ImageRgba16(buffer) =>
+        planar_f32(buffer, if normalize { 1.0 / u16::MAX as f32 } else { 1.0 }),
+      DynamicImage::ImageRgba32F(buffer) => planar_f32(buffer, 1.0),
+      other => planar_f32(&other.to_rgba32f(), 1.0),
+    }
+  }
+
+  /// WebP data URL of the image, ready to be used as an `<img>` source.
+  pub fn to_web_base64(&self) -> anyhow::Result<String>
+  {
+    match &self.img_buffer
+    {
+      DynamicImage::ImageRgba8(buffer) => base64_browser_img(buffer),
+      DynamicImage::ImageLuma8(buffer) => base64_browser_img(buffer),
+      other => base64_browser_img(&other.to_rgba8()),
+    }
   }
 }
 
 
+/// Splits interleaved pixels into one plane per channel, scaling every value.
+fn planar_f32<P: Pixel>(buffer: &ImageBuffer<P, Vec<P::Subpixel>>, scale: f32) -> Vec<f32>
+where
+  P::Subpixel: Into<f32>,
+{
+  let channels = P::CHANNEL_COUNT as usize;
+  let plane_len = (buffer.width() * buffer.height()) as usize;
+  let mut planes = vec![0.0; channels * plane_len];
+
+  for (index, pixel) in buffer.pixels().enumerate()
+  {
+    for (channel, &value) in pixel.channels().iter().enumerate()
+    {
+      planes[channel * plane_len + index] = value.into() * scale;
+    }
+  }
+
+  planes
+}
+
+
+pub const DEFAULT_JPEG_QUALITY: u8 = 90;
+
+
+fn is_jpeg(path: &Path) -> bool
+{
+  path.extension()
+    .and_then(|ext| ext.to_str())
+    .map_or(false, |ext| ext.eq_ignore_ascii_case("jpeg") || ext.eq_ignore_ascii_case("jpg"))
+}
+
+
+pub fn save_image(img: &DynamicImage, path: &Path, quality: Option<u8>) -> ImageResult<()>
+{
+  if !is_jpeg(path)
+  {
+    return img.save(path);
+  }
+
+  let quality = quality.unwrap_or(DEFAULT_JPEG_QUALITY).clamp(1, 100);
+  let rgb = img.to_rgb8();
+  let mut writer = BufWriter::new(File::create(path)?);
+
+  JpegEncoder::new_with_quality(&mut writer, quality)
+    .encode(rgb.as_raw(), rgb.width(), rgb.height(), ColorType::Rgb8)
+}
+
+
 #[derive(Debug, Default, Resource, Event)]
 pub struct SceneInfo
 {
@@ -88,7 +341,8 @@ fn next_power_of_2(n: usize) -> usize
 
 fn calculate_grid_dimensions(view_width: u32,
                              view_height: u32,
-                             num_views: u32)
+                             num_views: u32,
+                             power_of_two: bool)
   -> ((usize, usize), Vec<(u32, u32)>)
 {
   let cols = (num_views as f64).sqrt().ceil() as u32;
@@ -104,7 +358,7 @@ fn calculate_grid_dimensions(view_width: u32,
 
   let texture_width = {
     let is_already_power_of_2 = initial_texture_width & (initial_texture_width - 1) == 0;
-    if is_already_power_of_2
+    if is_already_power_of_2 || !power_of_two
     {
       initial_texture_width
     }
@@ -116,7 +370,7 @@ fn calculate_grid_dimensions(view_width: u32,
 
   let texture_height = {
     let is_already_power_of_2 = initial_texture_height & (initial_texture_height - 1) == 0;
-    if is_already_power_of_2
+    if is_already_power_of_2 || !power_of_two
     {
       initial_texture_height
     }
@@ -140,60 +394,307 @@ fn calculate_grid_dimensions(view_width: u32,
 }
 
 
+/// Render target options beyond the viewport layout.
+#[derive(Debug, Clone)]
+pub struct RenderTargetConfig
+{
+  /// Texture format of the render target. Use `Rgba16Float` or `Rgba32Float`
+  /// together with an `hdr` camera and `Tonemapping::None` to export
+  /// unclipped values, `Rgba16Unorm` for 16 bits per channel (needs the
+  /// `TEXTURE_FORMAT_16BIT_NORM` wgpu feature, saves losslessly as PNG) or
+  /// `R8Unorm` to copy a single channel per pixel.
+  pub format: TextureFormat,
+  /// Samples averaged into every exported pixel: 1, 4 or 16. Bevy's `Msaa`
+  /// is app wide, so instead the target is rendered at 2 or 4 times the width
+  /// and height and each block of pixels is averaged when a frame is copied
+  /// out. Only this target is affected, at the cost of rendering and copying
+  /// the larger texture. Cameras have to render into
+  /// `AtlasLayout::render_viewport`s.
+  pub samples: u32,
+  /// Round the texture size up to powers of two. Backends that don't need
+  /// it can use the tight `cols * view width` by `rows * view height` size
+  /// instead, saving memory and copy bandwidth.
+  pub power_of_two: bool,
+  /// Clear the whole target to this color once per frame, before any of the
+  /// cameras rendering into it. Their own clear colors only cover their
+  /// viewports, so with several views they should use
+  /// `ClearColorConfig::None` and rely on this instead.
+  pub clear_color: Option<Color>,
+  /// Number of most recent frames kept by the `ExportImage`, see
+  /// `ExportImage::history`. Anything above 1 costs a copy per frame.
+  pub history: usize,
+}
+
+
+impl Default for RenderTargetConfig
+{
+  fn default() -> Self
+  {
+    Self { format: TextureFormat::Rgba8UnormSrgb, samples: 1, power_of_two: true, clear_color: None, history: 1 }
+  }
+}
+
+
+impl RenderTargetConfig
+{
+  pub fn with_format(mut self, format: TextureFormat) -> Self
+  {
+    self.format = format;
+    self
+  }
+
+  pub fn with_samples(mut self, samples: u32) -> Self
+  {
+    self.samples = samples;
+    self
+  }
+
+  pub fn with_power_of_two(mut self, power_of_two: bool) -> Self
+  {
+    self.power_of_two = power_of_two;
+    self
+  }
+
+  pub fn with_clear_color(mut self, clear_color: Color) -> Self
+  {
+    self.clear_color = Some(clear_color);
+    self
+  }
+
+  pub fn with_history(mut self, history: usize) -> Self
+  {
+    self.history = history;
+    self
+  }
+}
+
+
+/// Camera that only clears the target of the export entity it is attached to.
+#[derive(Component, Debug)]
+struct ClearCamera(Entity);
+
+
+/// Width and height of the block of rendered pixels averaged into one
+/// exported pixel for `samples` samples per pixel.
+fn supersampling_from_samples(samples: u32) -> Result<u32, ExportError>
+{
+  match samples
+  {
+    1 => Ok(1),
+    4 => Ok(2),
+    16 => Ok(4),
+    _ => Err(ExportError::UnsupportedSampleCount(samples)),
+  }
+}
+
+
+/// Usages for a render target of the given format. Copying out and rendering
+/// are always requested, sampling and copying in only where the format
+/// guarantees them.
+fn render_target_usages(format: TextureFormat) -> TextureUsages
+{
+  let required = TextureUsages::COPY_SRC | TextureUsages::RENDER_ATTACHMENT;
+  let optional = TextureUsages::COPY_DST | TextureUsages::TEXTURE_BINDING;
+  let allowed = format.guaranteed_format_features(Features::empty()).allowed_usages;
+
+  if !allowed.contains(required)
+  {
+    log::warn!("Format {:?} is not guaranteed to support {:?}", format, required - allowed);
+  }
+
+  required | (optional & allowed)
+}
+
+
+/// Size of the images exported from a render target set up by
+/// `setup_render_target` and the top left corners of its viewports in them,
+/// in view order.
+#[derive(Debug, Clone, PartialEq)]
+pub struct AtlasLayout
+{
+  pub size: Extent3d,
+  pub viewports: Vec<(u32, u32)>,
+  /// How many times wider and higher the render target is than the exported
+  /// images, see `RenderTargetConfig::samples`.
+  pub render_scale: u32,
+}
+
+
+impl AtlasLayout
+{
+  /// Physical viewport camera `index` renders into, for views of
+  /// `viewport_size` in the exported images.
+  pub fn render_viewport(&self, index: usize, viewport_size: (u32, u32)) -> Option<Viewport>
+  {
+    let &(x, y) = self.viewports.get(index)?;
+    Some(Viewport
+    {
+      physical_position: UVec2::new(x, y) * self.render_scale,
+      physical_size: UVec2::new(viewport_size.0, viewport_size.1) * self.render_scale,
+      ..default()
+    })
+  }
+}
+
+
 pub fn setup_render_target(
-    target_name: &String,
+    settings: ImageExportSettings,
+    commands: &mut Commands,
+    images: &mut ResMut<Assets<Image>>,
+    export_sources: &mut ResMut<Assets<ImageSource>>,
+    exported_images: &mut ResMut<ExportedImages>,
+    viewport_size: (u32, u32),
+    num_views: u32,
+) -> Result<(RenderTarget, AtlasLayout), ExportError>
+{
+  setup_render_target_with_config(
+    settings,
+    commands,
+    images,
+    export_sources,
+    exported_images,
+    viewport_size,
+    num_views,
+    RenderTargetConfig::default(),
+  )
+}
+
+
+#[allow(clippy::too_many_arguments)]
+pub fn setup_render_target_with_config(
+    mut settings: ImageExportSettings,
     commands: &mut Commands,
     images: &mut ResMut<Assets<Image>>,
     export_sources: &mut ResMut<Assets<ImageSource>>,
     exported_images: &mut ResMut<ExportedImages>,
     viewport_size: (u32, u32),
     num_views: u32,
-) -> (RenderTarget, Vec<(u32, u32)>)
+    config: RenderTargetConfig,
+) -> Result<(RenderTarget, AtlasLayout), ExportError>
 {
-  let ((tex_width, tex_height), viewports) = calculate_grid_dimensions(viewport_size.0, viewport_size.1, num_views);
+  if num_views == 0
+  {
+    return Err(ExportError::NoViews);
+  }
+  if !ImageWrapper::supports_format(config.format)
+  {
+    return Err(ExportError::UnsupportedFormat(config.format));
+  }
+  let supersampling = supersampling_from_samples(config.samples)?;
+  settings.supersampling = supersampling;
+  bytes_per_row(config.format, viewport_size.0 * supersampling)?;
+
+  let ((tex_width, tex_height), viewports) = calculate_grid_dimensions(viewport_size.0, viewport_size.1, num_views, config.power_of_two);
   let size = Extent3d
   {
     width: tex_width as u32,
     height: tex_height as u32,
     ..Default::default()
   };
+  let render_size = Extent3d
+  {
+    width: size.width * supersampling,
+    height: size.height * supersampling,
+    ..Default::default()
+  };
 
-  log::info!("Texture size: {:?}, viewport size: {:?}, num views: {}", size, viewport_size, num_views);
+  log::info!("Texture size: {:?}, rendered at {:?}, format: {:?}, viewport size: {:?}, num views: {}",
+             size, render_size, config.format, viewport_size, num_views);
 
   let mut render_target_image = Image
   {
     texture_descriptor: TextureDescriptor
     {
       label: None,
-      size,
+      size: render_size,
       dimension: TextureDimension::D2,
-      format: TextureFormat::Rgba8UnormSrgb,
+      format: config.format,
       mip_level_count: 1,
       sample_count: 1,
-      usage: TextureUsages::COPY_SRC
-          | TextureUsages::COPY_DST
-          | TextureUsages::TEXTURE_BINDING
-          | TextureUsages::RENDER_ATTACHMENT,
+      usage: render_target_usages(config.format),
       view_formats: &[],
     },
     ..Default::default()
   };
-  render_target_image.resize(size);
+  render_target_image.resize(render_size);
   let render_target_image_handle = images.add(render_target_image);
 
-  let export_image = ExportImage::new(size);
+  let export_image = ExportImage::with_history(size, config.format, config.history);
   let mut locked_images = exported_images.0.lock();
-  locked_images.insert(target_name.clone(), export_image.clone());
+  locked_images.insert(settings.name.clone(), export_image.clone());
 
 //  log::info!("Setup exported images. It has {} images. Address of the container: {:?}", locked_images.len(), locked_images.as_ptr() as *const Vec<ExportImage>);
 
-  commands.spawn(ImageExportBundle {
+  let export_entity = commands.spawn(ImageExportBundle {
     source: export_sources.add(render_target_image_handle.clone()),
-    settings: ImageExportSettings::new(target_name.clone()),
+    settings,
     ..Default::default()
-  });
+  }).id();
 
-  (RenderTarget::Image(render_target_image_handle), viewports)
+  if let Some(clear_color) = config.clear_color
+  {
+    // Renders before every other camera and draws nothing, so all it does is
+    // clearing the target.
+    let clear_camera = commands.spawn((
+      Camera2dBundle
+      {
+        camera: Camera
+        {
+          order: isize::MIN,
+          target: RenderTarget::Image(render_target_image_handle.clone()),
+          clear_color: ClearColorConfig::Custom(clear_color),
+          ..default()
+        },
+        ..default()
+      },
+      RenderLayers::none(),
+    )).id();
+    commands.entity(export_entity).insert(ClearCamera(clear_camera));
+  }
+
+  Ok((RenderTarget::Image(render_target_image_handle), AtlasLayout { size, viewports, render_scale: supersampling }))
+}
+
+
+/// Stops exporting `target_name`: despawns its export entity and clear camera,
+/// drops the `ImageSource` so the GPU buffer is released and removes it from
+/// `ExportedImages`.
+pub fn remove_render_target(
+    target_name: &str,
+    commands: &mut Commands,
+    export_bundles: &Query<(Entity, &ImageExportSettings, &Handle<ImageSource>)>,
+    export_sources: &mut ResMut<Assets<ImageSource>>,
+    exported_images: &mut ResMut<ExportedImages>,
+)
+{
+  let removed_image = exported_images.0.lock().remove(target_name);
+
+  let mut found_bundle = false;
+  for (entity, settings, source_handle) in export_bundles.iter()
+  {
+    if settings.name == target_name
+    {
+      export_sources.remove(source_handle);
+      commands.add(move |world: &mut World| despawn_export(world, entity));
+      found_bundle = true;
+    }
+  }
+
+  if removed_image.is_none() && !found_bundle
+  {
+    log::warn!("No render target named {target_name} to remove");
+  }
+}
+
+
+/// Despawns an export entity together with its clear camera, if it has one.
+pub(crate) fn despawn_export(world: &mut World, entity: Entity)
+{
+  if let Some(&ClearCamera(clear_camera)) = world.get::<ClearCamera>(entity)
+  {
+    world.despawn(clear_camera);
+  }
+  world.despawn(entity);
 }
 
 
@@ -5,10 +5,11 @@ use bevy::{
     core_pipeline::{clear_color::ClearColor, core_3d::Camera3dBundle, tonemapping::Tonemapping},
     ecs::system::{Commands, Res, ResMut},
     math::Vec3,
-    render::{camera::{Camera, RenderTarget, Viewport}, color::Color, texture::Image},
-    transform::components::Transform
+    render::{camera::{Camera, RenderTarget}, color::Color, texture::Image},
+    transform::components::Transform,
+    utils::HashMap,
 };
-use gpu_copy::{setup_render_target, ImageSource, GpuToCpuCpyPlugin, ExportedImages};
+use gpu_copy::{setup_render_target, ImageSource, GpuToCpuCpyPlugin, ExportedImages, ImageExportSettings, ImageSaver, RunSteps, FramePathTemplate, DEFAULT_FRAME_PATH_TEMPLATE};
 
 
 fn setup(
@@ -21,22 +22,22 @@ fn setup(
 )
 {
   let viewport_size = (1024, 512);
-  let (render_target, viewport_pos) = setup_render_target(
-    &"minimal_example".to_string(),
+  let (render_target, layout) = setup_render_target(
+    ImageExportSettings::new("minimal_example".to_string()).with_quality(85),
     &mut commands,
     &mut images,
     &mut export_sources,
     &mut exported_images,
     viewport_size,
     1
-  );
+  ).expect("Failed to set up the export render target");
 
-  info!("viewport_pos: {:?}", viewport_pos);
+  info!("atlas size: {:?}, viewport_pos: {:?}", layout.size, layout.viewports);
 
-  match std::fs::create_dir("out")
+  match FramePathTemplate::new("out", DEFAULT_FRAME_PATH_TEMPLATE)
   {
-    Ok(_) => {}
-    Err(e) => log::error!("Couldn't create directory | {e:?}"),
+    Ok(frame_paths) => commands.insert_resource(frame_paths),
+    Err(e) => log::error!("Couldn't create the output directory | {e:?}"),
   }
 
   // circular base
@@ -69,11 +70,7 @@ fn setup(
       tonemapping: Tonemapping::None,
       camera: Camera {
         target: render_target,
-        viewport: Some(Viewport {
-          physical_position: UVec2::new(viewport_pos[0].0, viewport_pos[0].1),
-          physical_size: UVec2::new(viewport_size.0, viewport_size.1),
-          ..default()
-        }),
+        viewport: layout.render_viewport(0, viewport_size),
         ..default() },
       ..default()
   });
@@ -87,23 +84,23 @@ fn setup(
 
 
 fn save_img(exported_images: Res<ExportedImages>,
+            export_settings: Query<&ImageExportSettings>,
+            image_saver: Res<ImageSaver>,
+            frame_paths: Option<Res<FramePathTemplate>>,
+            mut last_saved: Local<HashMap<String, u64>>,
 )
 {
-  let locked_images = exported_images.0.lock();
-  if let Some(image) = &locked_images.get(&"minimal_example".to_string())
-  {
-    let image = &image.0.read();
-    let path = format!("out/minimal_example_{}.png", image.frame_id);
-    log::info!("path is {path}");
-    let img = image.img_buffer.clone();
+  let Some(frame_paths) = frame_paths else { return; };
 
-    std::thread::spawn(move ||
+  for settings in export_settings.iter()
+  {
+    let last_seen = last_saved.entry(settings.name.clone()).or_default();
+    if let Some(image) = exported_images.frame_if_newer(&settings.name, last_seen)
     {
-      if let Err(e) = img.save(path)
-      {
-        log::error!("Couldn't save image | {e:?}");
-      };
-    });
+      let path = frame_paths.path(&settings.name, image.frame_id, "jpeg");
+      log::info!("path is {path:?}");
+      image_saver.save_with_quality(path, image, settings.quality);
+    }
   }
 }
 
@@ -116,13 +113,24 @@ pub fn main()
       .filter_module("gpu_copy", log::LevelFilter::Info)
       .init();
 
-  Engine::new()
+  let mut engine = Engine::new();
+
+  // Optionally exit cleanly after the given number of frames,
+  // e.g. `cargo run -p minimal -- 100`.
+  if let Some(steps) = std::env::args().nth(1)
+  {
+    let steps = steps.parse().expect("The number of frames to run must be a positive integer");
+    engine.insert_resource(RunSteps(steps));
+  }
+
+  engine
       .insert_resource(ClearColor(Color::rgb_u8(0, 0, 0)))
       .add_plugins(DefaultPlugins)
       .add_plugins((
-          GpuToCpuCpyPlugin,
+          GpuToCpuCpyPlugin::default(),
           ScheduleRunnerPlugin::run_loop(std::time::Duration::from_secs_f64(1.0 / 30.0)),
       ))
+      .init_resource::<ImageSaver>()
       .add_systems(Startup, setup)
       .add_systems(Update, save_img)
       .run();